@@ -0,0 +1,15 @@
+#!/usr/bin/env bash
+# Regenerates every canister's .did file from its Rust interface (exported by `ic_cdk::export_candid!()`).
+# Requires the wasm32 target and candid-extractor: `cargo install candid-extractor`.
+# `cargo test` fails when a checked-in .did drifts from the Rust code; rerun this script to fix it.
+set -euo pipefail
+
+cd "$(dirname "$0")"
+
+CANISTERS=(data_aggregator inventory ledger ledger_archive price_engine)
+
+for canister in "${CANISTERS[@]}"; do
+  cargo build --target wasm32-unknown-unknown --release -p "$canister"
+  candid-extractor "target/wasm32-unknown-unknown/release/$canister.wasm" > "src/$canister/$canister.did"
+  echo "Generated src/$canister/$canister.did"
+done
@@ -0,0 +1,439 @@
+//! JSON ingestion for POS systems and other integrations pushing stock levels, sales and corrections.
+//!
+//! Payload schema (`ingest_json`):
+//!
+//! ```json
+//! {
+//!   "message_id": "pos-17-000123",
+//!   "items": [
+//!     {
+//!       "item_id": "SKU-1001",
+//!       "barcode": "4006381333931",
+//!       "name": "Whole Milk 1L",
+//!       "category": "Dairy",
+//!       "quantity": 42,
+//!       "unit": "each",
+//!       "expiration_date": "2024-06-30",
+//!       "price": 1.29,
+//!       "currency": "USD"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `category`, `unit`, `expiration_date` and `currency` are optional. `quantity` is the stock level
+//! after the sales being reported, since imports replace the whole item record. `unit`
+//! is one of "each", "kg", "g", "lb" or "L" (default "each"), `expiration_date` is YYYY-MM-DD, and
+//! `price` is per unit in major units of `currency` (default USD). Quantities and prices are read exactly
+//! as written, in plain decimal notation without exponents, and prices round half up to the nearest minor
+//! unit. Resending an identical payload returns the batch it created instead of importing it again, so
+//! `message_id` must differ between messages that should both be imported.
+//!
+//! Sales and stock corrections go to `ingest_movements` instead, as changes to items inventory already holds:
+//!
+//! ```json
+//! {
+//!   "message_id": "pos-17-000124",
+//!   "movements": [
+//!     { "type": "sale", "item_id": "SKU-1001", "quantity": 2, "price": 1.29, "currency": "USD" },
+//!     { "type": "adjustment", "item_id": "SKU-2040", "quantity": -0.5, "unit": "kg", "reason": "recount" },
+//!     { "type": "disposal", "item_id": "SKU-3110", "quantity": 4, "reason": "expired" }
+//!   ]
+//! }
+//! ```
+//!
+//! A sale lowers the stock by `quantity` sold at `price` per unit. An adjustment raises it by a positive
+//! `quantity` and lowers it by a negative one. A disposal writes off a positive `quantity` of spoiled or
+//! damaged stock and needs a `reason`. `unit` and `currency` default as above. Each applied movement is
+//! recorded in the ledger as a `Sale`, `StockAdjustment` or `WasteDisposal`. Resending a message returns its
+//! report without changing the stock again.
+//!
+//! Movements go through the validation rules on `item_id`, `quantity` and `price` but don't become batches.
+//! A batch row replaces an item record, so it can sit in quarantine and be resubmitted later; a movement
+//! changes stock relative to what inventory holds at the till, and applying it days later would land on
+//! top of newer sales. Lines failing a rule are reported and left out, and a corrected line goes in a new
+//! message.
+//!
+//! Callers authenticate either as the integration's principal or, from outside the IC, with
+//! `signature`: the hex HMAC-SHA256 of the exact payload bytes under the integration's key.
+
+use candid::{CandidType, Deserialize, Principal};
+use hmac::{Hmac, Mac};
+use serde_json::{Number, Value};
+use sha2::Sha256;
+use xero_types::money::{is_valid_currency, parse_decimal, DEFAULT_CURRENCY};
+use xero_types::inventory::StockMovement;
+use xero_types::units::MILLI_PER_UNIT;
+use xero_types::{InventoryItemInput, Money, Quantity, UnitOfMeasure};
+
+use crate::mapping::{parse_date, DEFAULT_DATE_FORMAT};
+use crate::RecordError;
+
+/// Most movements in one `ingest_movements` message.
+pub const MAX_MOVEMENTS: usize = 1_000;
+/// Shortest HMAC key accepted, the size of a SHA-256 digest.
+const MIN_HMAC_KEY_LEN: usize = 32;
+
+const UNITS: [UnitOfMeasure; 5] = [
+    UnitOfMeasure::Each,
+    UnitOfMeasure::Kilogram,
+    UnitOfMeasure::Gram,
+    UnitOfMeasure::Pound,
+    UnitOfMeasure::Liter,
+];
+
+/// A system allowed to push JSON payloads, identified by its principal, an HMAC key, or both.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Integration {
+    pub integration_id: String,
+    pub name: String,
+    pub principal: Option<Principal>,
+    pub hmac_key: Option<Vec<u8>>,
+}
+
+/// An integration as listed back to controllers, without its key.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct IntegrationInfo {
+    pub integration_id: String,
+    pub name: String,
+    pub principal: Option<Principal>,
+    pub has_hmac_key: bool,
+}
+
+impl From<&Integration> for IntegrationInfo {
+    fn from(integration: &Integration) -> Self {
+        IntegrationInfo {
+            integration_id: integration.integration_id.clone(),
+            name: integration.name.clone(),
+            principal: integration.principal,
+            has_hmac_key: integration.hmac_key.is_some(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Payload {
+    #[allow(dead_code)] // required so distinct messages never hash alike
+    message_id: String,
+    items: Vec<Value>, // parsed one by one so a bad item is quarantined rather than failing the payload
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiItem {
+    item_id: String,
+    barcode: String,
+    name: String,
+    category: Option<String>,
+    quantity: Number,
+    unit: Option<String>,
+    expiration_date: Option<String>,
+    price: Number,
+    currency: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct MovementPayload {
+    #[allow(dead_code)] // required so distinct messages never hash alike
+    message_id: String,
+    movements: Vec<Value>, // parsed one by one so a bad line is reported rather than failing the message
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ApiMovement {
+    Sale {
+        item_id: String,
+        quantity: Number,
+        unit: Option<String>,
+        price: Number,
+        currency: Option<String>,
+    },
+    Adjustment {
+        item_id: String,
+        quantity: Number, // negative for stock removed
+        unit: Option<String>,
+        reason: String,
+    },
+    Disposal {
+        item_id: String,
+        quantity: Number,
+        unit: Option<String>,
+        reason: String,
+    },
+}
+
+/// Checks that the integration can authenticate, returning the reason if not.
+pub fn check_integration(integration: &Integration) -> Result<(), String> {
+    if integration.integration_id.trim().is_empty() {
+        return Err("Integration ID cannot be empty".to_string());
+    }
+    match (&integration.principal, &integration.hmac_key) {
+        (None, None) => Err("An integration needs a principal, an HMAC key, or both".to_string()),
+        (_, Some(key)) if key.len() < MIN_HMAC_KEY_LEN => {
+            Err(format!("HMAC keys must be at least {} bytes", MIN_HMAC_KEY_LEN))
+        }
+        (Some(principal), _) if *principal == Principal::anonymous() => {
+            Err("The anonymous principal cannot identify an integration".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether the caller is the integration's principal or `signature` is the payload's HMAC under its key.
+pub fn is_authenticated(
+    integration: &Integration,
+    caller: Principal,
+    payload: &str,
+    signature: Option<&str>,
+) -> bool {
+    if integration.principal == Some(caller) {
+        return true;
+    }
+    let (Some(key), Some(signature)) = (&integration.hmac_key, signature) else {
+        return false;
+    };
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(key) else {
+        return false;
+    };
+    mac.update(payload.as_bytes());
+    // Constant-time comparison
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Items of the payload in order; items in the returned errors hold defaults where a value was unusable.
+pub fn parse_payload(payload: &str) -> Result<(Vec<InventoryItemInput>, Vec<RecordError>), String> {
+    let payload: Payload = serde_json::from_str(payload).map_err(|e| format!("Invalid payload: {}", e))?;
+
+    let mut items = Vec::with_capacity(payload.items.len());
+    let mut errors = Vec::new();
+    for (row, value) in payload.items.into_iter().enumerate() {
+        let item_id = value.get("item_id").and_then(Value::as_str).unwrap_or_default().to_string();
+        match serde_json::from_value::<ApiItem>(value).map_err(|e| e.to_string()).and_then(to_input) {
+            Ok(item) => items.push(item),
+            Err(message) => {
+                items.push(InventoryItemInput {
+                    item_id: item_id.clone(),
+                    barcode: String::new(),
+                    name: String::new(),
+                    category: None,
+                    quantity: Quantity::whole(0, UnitOfMeasure::Each),
+                    expiration_date: 0,
+                    price: Money::new(0, DEFAULT_CURRENCY),
+                });
+                errors.push(RecordError {
+                    row_index: row as u32,
+                    item_id,
+                    rule_id: None,
+                    message,
+                });
+            }
+        }
+    }
+    Ok((items, errors))
+}
+
+/// Movements of a message with their index in it, and errors for the lines that can't be used.
+pub type ParsedMovements = (Vec<(u32, StockMovement)>, Vec<RecordError>);
+
+pub fn parse_movements(payload: &str) -> Result<ParsedMovements, String> {
+    let payload: MovementPayload =
+        serde_json::from_str(payload).map_err(|e| format!("Invalid payload: {}", e))?;
+    if payload.movements.is_empty() || payload.movements.len() > MAX_MOVEMENTS {
+        return Err(format!("A message holds between 1 and {} movements", MAX_MOVEMENTS));
+    }
+
+    let mut movements = Vec::with_capacity(payload.movements.len());
+    let mut errors = Vec::new();
+    for (row, value) in payload.movements.into_iter().enumerate() {
+        let item_id = value.get("item_id").and_then(Value::as_str).unwrap_or_default().to_string();
+        match serde_json::from_value::<ApiMovement>(value).map_err(|e| e.to_string()).and_then(to_movement) {
+            Ok(movement) => movements.push((row as u32, movement)),
+            Err(message) => errors.push(RecordError {
+                row_index: row as u32,
+                item_id,
+                rule_id: None,
+                message,
+            }),
+        }
+    }
+    Ok((movements, errors))
+}
+
+fn to_movement(movement: ApiMovement) -> Result<StockMovement, String> {
+    match movement {
+        ApiMovement::Sale { item_id, quantity, unit, price, currency } => {
+            let quantity = parse_quantity(&quantity, unit.as_deref())?;
+            if quantity.is_zero() {
+                return Err("Quantity sold must be positive".to_string());
+            }
+            let unit_price = parse_price(&price, currency.as_deref())?;
+            Ok(StockMovement::Sale { item_id, quantity, unit_price })
+        }
+        ApiMovement::Adjustment { item_id, quantity, unit, reason } => {
+            let text = quantity.to_string();
+            let (increase, magnitude) = match text.strip_prefix('-') {
+                Some(magnitude) => (false, magnitude),
+                None => (true, text.as_str()),
+            };
+            let quantity = quantity_from_decimal(magnitude, unit.as_deref())?;
+            if quantity.is_zero() {
+                return Err("Adjustment quantity cannot be zero".to_string());
+            }
+            if reason.trim().is_empty() {
+                return Err("Adjustments need a reason".to_string());
+            }
+            Ok(StockMovement::Adjustment { item_id, quantity, increase, reason })
+        }
+        ApiMovement::Disposal { item_id, quantity, unit, reason } => {
+            let quantity = parse_quantity(&quantity, unit.as_deref())?;
+            if quantity.is_zero() {
+                return Err("Quantity disposed must be positive".to_string());
+            }
+            if reason.trim().is_empty() {
+                return Err("Disposals need a reason".to_string());
+            }
+            Ok(StockMovement::Disposal { item_id, quantity, reason })
+        }
+    }
+}
+
+fn parse_quantity(value: &Number, unit: Option<&str>) -> Result<Quantity, String> {
+    quantity_from_decimal(&value.to_string(), unit)
+}
+
+// `arbitrary_precision` keeps JSON numbers as written, so they go through the same decimal parser as file columns
+fn quantity_from_decimal(value: &str, unit: Option<&str>) -> Result<Quantity, String> {
+    let unit = match unit {
+        None => UnitOfMeasure::Each,
+        Some(symbol) => UNITS
+            .into_iter()
+            .find(|unit| unit.symbol().eq_ignore_ascii_case(symbol))
+            .ok_or_else(|| format!("Unknown unit '{}'", symbol))?,
+    };
+    let milli_units = parse_decimal(value, MILLI_PER_UNIT.ilog10())
+        .ok_or_else(|| format!("Quantity {} must be a non-negative decimal in range", value))?;
+    Ok(Quantity { milli_units, unit })
+}
+
+fn parse_price(value: &Number, currency: Option<&str>) -> Result<Money, String> {
+    let currency = currency.unwrap_or(DEFAULT_CURRENCY);
+    if !is_valid_currency(currency) {
+        return Err(format!("Unknown currency '{}'", currency));
+    }
+    Money::from_major_str(&value.to_string(), currency).ok_or_else(|| format!("Price {} is not valid", value))
+}
+
+fn to_input(item: ApiItem) -> Result<InventoryItemInput, String> {
+    let quantity = parse_quantity(&item.quantity, item.unit.as_deref())?;
+    let price = parse_price(&item.price, item.currency.as_deref())?;
+    let expiration_date = match &item.expiration_date {
+        Some(date) => parse_date(date, DEFAULT_DATE_FORMAT)?,
+        None => 0, // inventory falls back to the category's shelf life
+    };
+
+    Ok(InventoryItemInput {
+        item_id: item.item_id,
+        barcode: item.barcode,
+        name: item.name,
+        category: item.category,
+        quantity,
+        expiration_date,
+        price,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movements_parse_sales_and_signed_adjustments() {
+        let payload = r#"{"message_id": "pos-1", "movements": [
+            {"type": "sale", "item_id": "SKU-1", "quantity": 2, "price": 1.29},
+            {"type": "adjustment", "item_id": "SKU-2", "quantity": -0.5, "unit": "kg", "reason": "damaged"},
+            {"type": "adjustment", "item_id": "SKU-3", "quantity": 0, "reason": "recount"},
+            {"type": "sale", "item_id": "SKU-4", "quantity": 1, "price": 2, "discount": 1}
+        ]}"#;
+        let (movements, errors) = parse_movements(payload).unwrap();
+
+        assert_eq!(
+            movements,
+            vec![
+                (
+                    0,
+                    StockMovement::Sale {
+                        item_id: "SKU-1".to_string(),
+                        quantity: Quantity::whole(2, UnitOfMeasure::Each),
+                        unit_price: Money::new(129, "USD"),
+                    }
+                ),
+                (
+                    1,
+                    StockMovement::Adjustment {
+                        item_id: "SKU-2".to_string(),
+                        quantity: Quantity { milli_units: 500, unit: UnitOfMeasure::Kilogram },
+                        increase: false,
+                        reason: "damaged".to_string(),
+                    }
+                ),
+            ]
+        );
+        let rejected: Vec<(u32, &str)> = errors.iter().map(|e| (e.row_index, e.item_id.as_str())).collect();
+        assert_eq!(rejected, vec![(2, "SKU-3"), (3, "SKU-4")]);
+    }
+
+    #[test]
+    fn numbers_are_read_as_written() {
+        let payload = r#"{"message_id": "pos-4", "movements": [
+            {"type": "sale", "item_id": "SKU-1", "quantity": 0.1, "unit": "kg", "price": 1.005},
+            {"type": "sale", "item_id": "SKU-2", "quantity": 1, "price": 1e2}
+        ]}"#;
+        let (movements, errors) = parse_movements(payload).unwrap();
+        let StockMovement::Sale { quantity, unit_price, .. } = &movements[0].1 else {
+            panic!("expected a sale");
+        };
+        assert_eq!(*quantity, Quantity { milli_units: 100, unit: UnitOfMeasure::Kilogram });
+        assert_eq!(*unit_price, Money::new(101, "USD"));
+        // Exponents aren't plain decimals
+        assert_eq!(errors.iter().map(|e| e.row_index).collect::<Vec<_>>(), vec![1]);
+
+        let items = r#"{"message_id": "pos-5", "items": [{"item_id": "SKU-1", "barcode": "", "name": "Milk",
+            "quantity": 12, "price": 0.295}]}"#;
+        let (items, errors) = parse_payload(items).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(items[0].price, Money::new(30, "USD"));
+    }
+
+    #[test]
+    fn disposals_need_a_positive_quantity_and_a_reason() {
+        let payload = r#"{"message_id": "pos-6", "movements": [
+            {"type": "disposal", "item_id": "SKU-1", "quantity": 4, "reason": "expired"},
+            {"type": "disposal", "item_id": "SKU-2", "quantity": 0, "reason": "expired"},
+            {"type": "disposal", "item_id": "SKU-3", "quantity": 1, "reason": " "}
+        ]}"#;
+        let (movements, errors) = parse_movements(payload).unwrap();
+        assert_eq!(movements.len(), 1);
+        assert!(matches!(&movements[0].1, StockMovement::Disposal { reason, .. } if reason == "expired"));
+        assert_eq!(errors.iter().map(|e| e.row_index).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn empty_movement_messages_are_rejected() {
+        assert!(parse_movements(r#"{"message_id": "pos-2", "movements": []}"#).is_err());
+        assert!(parse_movements(r#"{"message_id": "pos-3"}"#).is_err());
+    }
+}
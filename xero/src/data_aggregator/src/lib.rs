@@ -1,18 +1,46 @@
-use candid::{CandidType, Deserialize};
+use candid::types::value::IDLValue;
+use candid::{CandidType, Deserialize, Principal};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use xero_types::inventory::InventoryError;
+use xero_types::money::DEFAULT_CURRENCY;
+use xero_types::{
+    CallFailure, Dependencies, ErrorClass, InventoryItemInput, Money, Quantity, TransactionKind, UnitOfMeasure,
+};
+
+mod api;
+mod manual;
+mod mapping;
+mod movements;
+mod stats;
+mod sync;
+mod upload;
+mod validation;
+
+use api::{Integration, IntegrationInfo, ParsedMovements};
+use manual::ManualEntry;
+use mapping::{MappingPreview, MappingProfile};
+use movements::MovementReport;
+use stats::ProcessingStatistics;
+use sync::{SyncReport, SyncStatus};
+use upload::{UploadProgress, UploadSession, UploadStatus};
+use validation::{RuleSet, ValidationRule};
 
 // Type definitions
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub enum ProcessingStatus {
+    Draft, // manual entry awaiting review
     Pending,
     Processing,
     Completed,
+    PartiallyCompleted, // some records imported, the rest quarantined
     Failed,
+    Rejected, // manual entry turned down in review
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
 pub enum DataSource {
     Excel,
     CSV,
@@ -20,14 +48,6 @@ pub enum DataSource {
     Manual,
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
-pub struct ValidationRule {
-    rule_id: String,
-    field: String,
-    condition: String,
-    error_message: String,
-}
-
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct ProcessedData {
     batch_id: String,
@@ -36,159 +56,943 @@ pub struct ProcessedData {
     status: ProcessingStatus,
     records_count: u32,
     success_count: u32,
-    error_count: u32,
-    validation_errors: Vec<String>,
+    error_count: u32, // records quarantined in the batch
+    validation_errors: Vec<RecordError>,
+}
+
+/// Why one record of a batch was not imported.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RecordError {
+    row_index: u32,
+    item_id: String,
+    rule_id: Option<String>, // None for checks outside the validation rules, e.g. unknown categories
+    message: String,
+}
+
+/// A quarantined record corrected by staff, replacing the row at `row_index`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CorrectedRecord {
+    row_index: u32,
+    item: InventoryItemInput,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct DataBatch {
     batch_id: String,
-    data: Vec<InventoryItem>,
+    source: DataSource,
+    data: Vec<InventoryItemInput>,
     status: ProcessingStatus,
     created_at: u64,
     processed_at: Option<u64>,
+    record_errors: Vec<RecordError>, // rows listed here are quarantined; every other row was imported
+    content_hash: Option<String>, // hex SHA-256 of the uploaded file, used to spot re-uploads
+    profile_id: Option<String>, // mapping profile the file was parsed with
+    manual: Option<ManualEntry>, // set for batches entered by hand
+    processing_nanos: Option<u64>, // time spent in processing runs, summed over retries
 }
 
+/// A parsed upload about to become a batch; rows in `parse_errors` start out quarantined.
+struct NewBatch {
+    source: DataSource,
+    data: Vec<InventoryItemInput>,
+    parse_errors: Vec<RecordError>,
+    content_hash: Option<String>,
+    profile_id: Option<String>,
+    manual: Option<ManualEntry>, // manual batches start as drafts
+}
+
+/// Errors returned by the data aggregator canister API.
 #[derive(CandidType, Deserialize, Clone, Debug)]
-pub struct InventoryItem {
-    item_id: String,
-    barcode: String,
-    name: String,
-    category: Option<String>,
-    quantity: u32,
-    expiration_date: u64,
-    price: f64,
+pub enum AggregatorError {
+    BatchNotFound { batch_id: String },
+    InvalidUpload { reason: String },
+    ValidationFailed { errors: Vec<String> },
+    InventoryUpdateFailed { reason: String },
+    RuleNotFound { rule_id: String },
+    InvalidRule { rule_id: String, reason: String },
+    ProfileNotFound { profile_id: String },
+    InvalidProfile { profile_id: String, reason: String },
+    IntegrationNotFound { integration_id: String },
+    InvalidIntegration { integration_id: String, reason: String },
+    Unauthorized { caller: Principal },
+    UploadNotFound { session_id: String },
+    /// The assembled file doesn't match the SHA-256 announced in `begin_upload`.
+    HashMismatch { expected: String, actual: String },
+    /// Too many uploads are open, for the caller or overall; commit or wait for some before starting another.
+    UploadLimitReached { reason: String },
+    SyncInProgress,
+    /// Manual batches must be approved by someone other than their author and last editor.
+    SecondReviewerRequired { batch_id: String },
+    LedgerRejected { reason: String },
+    /// The batch is in a state that doesn't allow the operation, e.g. already being processed.
+    InvalidBatchState { batch_id: String, status: ProcessingStatus },
+    /// The sibling canister has not been configured through the init argument or `set_dependencies`.
+    DependencyNotConfigured { dependency: String },
+    /// A call to a sibling canister was rejected.
+    CallFailed(CallFailure),
+}
+
+impl From<CallFailure> for AggregatorError {
+    fn from(failure: CallFailure) -> Self {
+        AggregatorError::CallFailed(failure)
+    }
 }
 
+impl ErrorClass for AggregatorError {
+    fn code(&self) -> &'static str {
+        match self {
+            AggregatorError::BatchNotFound { .. } => "batch_not_found",
+            AggregatorError::InvalidUpload { .. } => "invalid_upload",
+            AggregatorError::ValidationFailed { .. } => "validation_failed",
+            AggregatorError::InventoryUpdateFailed { .. } => "inventory_update_failed",
+            AggregatorError::RuleNotFound { .. } => "rule_not_found",
+            AggregatorError::InvalidRule { .. } => "invalid_rule",
+            AggregatorError::ProfileNotFound { .. } => "profile_not_found",
+            AggregatorError::InvalidProfile { .. } => "invalid_profile",
+            AggregatorError::IntegrationNotFound { .. } => "integration_not_found",
+            AggregatorError::InvalidIntegration { .. } => "invalid_integration",
+            AggregatorError::Unauthorized { .. } => "unauthorized",
+            AggregatorError::UploadNotFound { .. } => "upload_not_found",
+            AggregatorError::HashMismatch { .. } => "hash_mismatch",
+            AggregatorError::UploadLimitReached { .. } => "upload_limit_reached",
+            AggregatorError::SyncInProgress => "sync_in_progress",
+            AggregatorError::SecondReviewerRequired { .. } => "second_reviewer_required",
+            AggregatorError::LedgerRejected { .. } => "ledger_rejected",
+            AggregatorError::InvalidBatchState { .. } => "invalid_batch_state",
+            AggregatorError::DependencyNotConfigured { .. } => "dependency_not_configured",
+            AggregatorError::CallFailed(_) => "call_failed",
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            // Both clear up on their own once the running sync or the open uploads finish
+            AggregatorError::SyncInProgress | AggregatorError::UploadLimitReached { .. } => true,
+            AggregatorError::CallFailed(failure) => failure.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+impl AggregatorError {
+    fn invalid_batch_state(batch: &DataBatch) -> Self {
+        AggregatorError::InvalidBatchState {
+            batch_id: batch.batch_id.clone(),
+            status: batch.status,
+        }
+    }
+
+    fn dependency_not_configured(dependency: &str) -> Self {
+        AggregatorError::DependencyNotConfigured {
+            dependency: dependency.to_string(),
+        }
+    }
+}
+
+/// Most items sent to inventory's `import_items` in one call.
+const IMPORT_CHUNK_SIZE: usize = 500;
+/// Most batches returned by one `list_batches` call.
+const MAX_BATCHES_PER_PAGE: u64 = 100;
+/// Rows parsed by `preview_mapping`.
+const PREVIEW_ROWS: usize = 20;
+/// Settled batches are dropped this long after they were last processed.
+const BATCH_RETENTION_NANOS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+/// Most records kept across all batches, bounding the heap and what `pre_upgrade` has to serialize.
+const MAX_STORED_RECORDS: usize = 250_000;
+
 // State management
 thread_local! {
     static BATCHES: RefCell<HashMap<String, DataBatch>> = RefCell::new(HashMap::new());
+    static BATCH_COUNTER: Cell<u64> = const { Cell::new(0) };
+    // upload_key -> batch_id, rebuilt from BATCHES after an upgrade
+    static UPLOAD_HASHES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    // Chunked uploads in flight; not kept across upgrades
+    static UPLOADS: RefCell<HashMap<String, UploadSession>> = RefCell::new(HashMap::new());
     static VALIDATION_RULES: RefCell<HashMap<String, ValidationRule>> = RefCell::new(HashMap::new());
-    static PROCESSING_STATS: RefCell<ProcessingStatistics> = RefCell::new(ProcessingStatistics::default());
-}
-
-#[derive(CandidType, Deserialize, Clone, Debug, Default)]
-pub struct ProcessingStatistics {
-    total_batches: u64,
-    successful_batches: u64,
-    failed_batches: u64,
-    total_records: u64,
-    success_rate: f64,
+    static MAPPING_PROFILES: RefCell<HashMap<String, MappingProfile>> = RefCell::new(HashMap::new());
+    static SYNC_STATUS: RefCell<SyncStatus> = RefCell::new(SyncStatus::default());
+    static INTEGRATIONS: RefCell<HashMap<String, Integration>> = RefCell::new(HashMap::new());
+    // Outcomes of `ingest_movements` messages, keyed by integration and payload hash
+    static MOVEMENT_REPORTS: RefCell<HashMap<String, MovementReport>> = RefCell::new(HashMap::new());
+    // Besides controllers, who may approve or reject manual batches
+    static REVIEWERS: RefCell<Vec<Principal>> = const { RefCell::new(Vec::new()) };
+    // Batches settled before this time are left out of the statistics
+    static STATS_RESET_AT: Cell<u64> = const { Cell::new(0) };
+    static DEPENDENCIES: RefCell<Dependencies> = RefCell::new(Dependencies::default());
 }
 
 // Implementation
 #[init]
-fn init() {
+fn init(dependencies: Option<Dependencies>) {
+    DEPENDENCIES.with(|deps| {
+        *deps.borrow_mut() = dependencies.unwrap_or_default();
+    });
     ic_cdk::println!("Data Aggregator initialized");
 }
 
+/// Imports an Excel file as a new batch. Uploading a file identical to an earlier one returns that
+/// batch's status instead of importing its stock twice; use `retry_failed_batch` to process it again.
+/// Open to the same callers as `begin_upload`.
 #[update]
-async fn upload_inventory_excel(data: Vec<u8>) -> Result<ProcessedData, String> {
-    let batch_id = generate_batch_id();
-    let now = ic_cdk::api::time();
+async fn upload_inventory_excel(data: Vec<u8>) -> Result<ProcessedData, AggregatorError> {
+    upload_file(data, None).await
+}
 
-    // Process Excel data
-    match process_excel_data(&data).await {
-        Ok(items) => {
-            let batch = DataBatch {
-                batch_id: batch_id.clone(),
-                data: items.clone(),
-                status: ProcessingStatus::Pending,
-                created_at: now,
-                processed_at: None,
-            };
+/// Imports a delimited text export through a saved mapping profile, deduplicated like
+/// `upload_inventory_excel`. Rows that can't be parsed are quarantined along with those failing validation.
+#[update]
+async fn upload_inventory_csv(data: Vec<u8>, profile_id: String) -> Result<ProcessedData, AggregatorError> {
+    upload_file(data, Some(profile_id)).await
+}
 
-            BATCHES.with(|batches| {
-                batches.borrow_mut().insert(batch_id.clone(), batch);
-            });
+/// Imports a JSON payload pushed by a registered integration, in the schema documented in `api.rs`.
+/// The caller must be the integration's principal, or `signature` the hex HMAC-SHA256 of `payload`
+/// under its key. Invalid items are quarantined like rows of an uploaded file.
+#[update]
+async fn ingest_json(
+    integration_id: String,
+    payload: String,
+    signature: Option<String>,
+) -> Result<ProcessedData, AggregatorError> {
+    authenticate_integration(integration_id, &payload, signature.as_deref())?;
+    let (data, parse_errors) =
+        api::parse_payload(&payload).map_err(|reason| AggregatorError::InvalidUpload { reason })?;
+    let batch = NewBatch {
+        source: DataSource::API,
+        data,
+        parse_errors,
+        content_hash: Some(sha256_hex(payload.as_bytes())),
+        profile_id: None,
+        manual: None,
+    };
+    let batch_id = match register_batch(batch)? {
+        Registered::New(batch_id) => batch_id,
+        // A resent message; its batch already holds the items
+        Registered::Existing(existing) => return Ok(existing),
+    };
+    process_pending(batch_id).await
+}
 
-            // Process the batch
-            process_batch(batch_id).await
+/// Applies sales and stock corrections pushed by a registered integration, in the schema documented in
+/// `api.rs`, and records each applied line in the ledger. Callers authenticate as for `ingest_json`.
+/// Lines failing a validation rule on the item ID, quantity or price are reported and not applied.
+/// Resending a message returns its report without changing the stock again, recording any lines the
+/// ledger missed the first time.
+#[update]
+async fn ingest_movements(
+    integration_id: String,
+    payload: String,
+    signature: Option<String>,
+) -> Result<MovementReport, AggregatorError> {
+    let integration = authenticate_integration(integration_id, &payload, signature.as_deref())?;
+    let message_key = format!("{}:{}", integration.integration_id, sha256_hex(payload.as_bytes()));
+
+    if movements::report(&message_key).is_none() {
+        let parsed = api::parse_movements(&payload).map_err(|reason| AggregatorError::InvalidUpload { reason })?;
+        let (parsed, errors) = validate_movements(parsed);
+        // Stored before the first await, so a resend arriving meanwhile doesn't apply the message again
+        if movements::insert_report(MovementReport::new(message_key.clone(), parsed, errors)) {
+            movements::apply(&message_key).await?;
         }
-        Err(e) => Err(format!("Failed to process Excel data: {}", e)),
     }
+    movements::record(&message_key, &format!("integration:{}", integration.integration_id)).await;
+    movements::report(&message_key).ok_or(AggregatorError::InvalidUpload {
+        reason: "The message was dropped while being applied; send it again".to_string(),
+    })
+}
+
+// The caller must be the integration's principal, or `signature` the HMAC of `payload` under its key
+fn authenticate_integration(
+    integration_id: String,
+    payload: &str,
+    signature: Option<&str>,
+) -> Result<Integration, AggregatorError> {
+    let integration = INTEGRATIONS
+        .with(|integrations| integrations.borrow().get(&integration_id).cloned())
+        .ok_or(AggregatorError::IntegrationNotFound { integration_id })?;
+    let caller = ic_cdk::caller();
+    if !api::is_authenticated(&integration, caller, payload, signature) {
+        return Err(AggregatorError::Unauthorized { caller });
+    }
+    Ok(integration)
+}
+
+/// Records a transaction in the ledger under an idempotency key and returns its ID.
+async fn record_in_ledger(
+    kind: TransactionKind,
+    actor_id: String,
+    idempotency_key: String,
+) -> Result<u64, AggregatorError> {
+    let ledger = ledger_canister_id()?;
+    // LedgerError is specific to the ledger canister; it is only shown, never matched on
+    let (result,): (Result<u64, IDLValue>,) =
+        ic_cdk::api::call::call(ledger, "record_transaction", (kind, actor_id, Some(idempotency_key)))
+            .await
+            .map_err(|e| CallFailure::new(ledger, "record_transaction", e))?;
+    result.map_err(|error| AggregatorError::LedgerRejected {
+        reason: error.to_string(),
+    })
+}
+
+async fn upload_file(data: Vec<u8>, profile_id: Option<String>) -> Result<ProcessedData, AggregatorError> {
+    require_uploader()?;
+    let key = upload_key(&sha256_hex(&data), profile_id.as_deref());
+    if let Some(existing) = batch_for_upload(&key) {
+        return Ok(existing);
+    }
+
+    let batch = parse_file(&data, profile_id).await?;
+
+    // Checked again, since another upload of the same file may have landed while parsing
+    let batch_id = match register_batch(batch)? {
+        Registered::New(batch_id) => batch_id,
+        Registered::Existing(existing) => return Ok(existing),
+    };
+
+    // Process the batch
+    process_pending(batch_id).await
 }
 
+/// Reads an uploaded file: as delimited text through the mapping profile if one is given, otherwise as Excel.
+async fn parse_file(data: &[u8], profile_id: Option<String>) -> Result<NewBatch, AggregatorError> {
+    let content_hash = Some(sha256_hex(data));
+    let Some(profile_id) = profile_id else {
+        // Process Excel data
+        return Ok(NewBatch {
+            source: DataSource::Excel,
+            data: process_excel_data(data).await?,
+            parse_errors: Vec::new(),
+            content_hash,
+            profile_id: None,
+            manual: None,
+        });
+    };
+
+    let profile = mapping_profile(&profile_id)?;
+    let parsed =
+        mapping::parse(data, &profile, None).map_err(|reason| AggregatorError::InvalidUpload { reason })?;
+    Ok(NewBatch {
+        source: DataSource::CSV,
+        data: parsed.items,
+        parse_errors: parsed.errors,
+        content_hash,
+        profile_id: Some(profile_id),
+        manual: None,
+    })
+}
+
+fn mapping_profile(profile_id: &str) -> Result<MappingProfile, AggregatorError> {
+    MAPPING_PROFILES
+        .with(|profiles| profiles.borrow().get(profile_id).cloned())
+        .ok_or_else(|| AggregatorError::ProfileNotFound { profile_id: profile_id.to_string() })
+}
+
+/// Opens a session for uploading a file larger than one message; send it with `upload_chunk` and
+/// finish with `commit_upload`. `sha256` is the hex digest of the whole file; files sent with a
+/// `profile_id` are parsed as delimited text through that mapping profile, others as Excel.
+/// Open to controllers, reviewers and the principals of registered integrations, within the upload limits.
 #[update]
-async fn process_batch(batch_id: String) -> Result<ProcessedData, String> {
-    let batch = BATCHES.with(|batches| {
-        batches
-            .borrow()
-            .get(&batch_id)
-            .cloned()
-            .ok_or_else(|| "Batch not found".to_string())
+fn begin_upload(
+    filename: String,
+    size: u64,
+    sha256: String,
+    profile_id: Option<String>,
+) -> Result<String, AggregatorError> {
+    let owner = require_uploader()?;
+    if size == 0 || size > upload::MAX_UPLOAD_SIZE {
+        return Err(AggregatorError::InvalidUpload {
+            reason: format!("File size must be between 1 and {} bytes", upload::MAX_UPLOAD_SIZE),
+        });
+    }
+    if !upload::is_sha256_hex(&sha256) {
+        return Err(AggregatorError::InvalidUpload {
+            reason: "sha256 must be 64 hex digits".to_string(),
+        });
+    }
+    if let Some(profile_id) = &profile_id {
+        mapping_profile(profile_id)?;
+    }
+
+    let now = ic_cdk::api::time();
+    let session_id = generate_id("UPLOAD");
+    UPLOADS.with(|uploads| {
+        let mut uploads = uploads.borrow_mut();
+        uploads.retain(|_, session| !session.is_expired(now));
+        upload::check_limits(&uploads, owner, size)?;
+        uploads.insert(
+            session_id.clone(),
+            UploadSession {
+                owner,
+                filename,
+                size,
+                sha256: sha256.to_ascii_lowercase(),
+                profile_id,
+                chunks: Default::default(),
+                status: UploadStatus::Receiving,
+                created_at: now,
+            },
+        );
+        Ok(session_id)
+    })
+}
+
+/// Stores chunk `index` (numbered from 0) of the file; sending an index again replaces it.
+#[update]
+fn upload_chunk(session_id: String, index: u32, bytes: Vec<u8>) -> Result<UploadProgress, AggregatorError> {
+    if bytes.is_empty() || bytes.len() > upload::MAX_CHUNK_SIZE {
+        return Err(AggregatorError::InvalidUpload {
+            reason: format!("Chunks must be between 1 and {} bytes", upload::MAX_CHUNK_SIZE),
+        });
+    }
+    with_upload(&session_id, |session| {
+        if session.status != UploadStatus::Receiving {
+            return Err(AggregatorError::InvalidUpload {
+                reason: "Upload has already been committed".to_string(),
+            });
+        }
+        let replaced = session.chunks.get(&index).map_or(0, |chunk| chunk.len() as u64);
+        if session.received_bytes() - replaced + bytes.len() as u64 > session.size {
+            return Err(AggregatorError::InvalidUpload {
+                reason: format!("Chunks exceed the announced size of {} bytes", session.size),
+            });
+        }
+        session.chunks.insert(index, bytes);
+        Ok(session.progress(&session_id))
+    })
+}
+
+/// Checks the file against its announced size and hash, then parses and imports it in the background;
+/// poll `get_upload_progress` for the resulting batch.
+#[update]
+fn commit_upload(session_id: String) -> Result<UploadProgress, AggregatorError> {
+    let (data, profile_id, progress) = with_upload(&session_id, |session| {
+        if session.status != UploadStatus::Receiving {
+            return Err(AggregatorError::InvalidUpload {
+                reason: "Upload has already been committed".to_string(),
+            });
+        }
+        let data = session.assemble()?;
+        session.chunks.clear();
+        session.status = UploadStatus::Parsing;
+        Ok((data, session.profile_id.clone(), session.progress(&session_id)))
     })?;
 
-    // Validate data
-    let validation_results = validate_batch(&batch);
-    
-    // Update inventory if validation passes
-    if validation_results.is_empty() {
-        match update_inventory(&batch).await {
-            Ok(_) => {
-                let processed_data = ProcessedData {
-                    batch_id: batch_id.clone(),
-                    source: DataSource::Excel,
-                    timestamp: ic_cdk::api::time(),
-                    status: ProcessingStatus::Completed,
-                    records_count: batch.data.len() as u32,
-                    success_count: batch.data.len() as u32,
-                    error_count: 0,
-                    validation_errors: vec![],
-                };
-
-                update_processing_stats(true, batch.data.len());
-                
-                Ok(processed_data)
+    upload::schedule_parse(session_id, data, profile_id);
+    Ok(progress)
+}
+
+#[query]
+fn get_upload_progress(session_id: String) -> Result<UploadProgress, AggregatorError> {
+    with_upload(&session_id, |session| Ok(session.progress(&session_id)))
+}
+
+// Sessions are private to the principal that opened them
+fn with_upload<T>(
+    session_id: &str,
+    f: impl FnOnce(&mut UploadSession) -> Result<T, AggregatorError>,
+) -> Result<T, AggregatorError> {
+    UPLOADS.with(|uploads| {
+        let mut uploads = uploads.borrow_mut();
+        let session = uploads
+            .get_mut(session_id)
+            .ok_or_else(|| AggregatorError::UploadNotFound { session_id: session_id.to_string() })?;
+        let caller = ic_cdk::caller();
+        if session.owner != caller {
+            return Err(AggregatorError::Unauthorized { caller });
+        }
+        f(session)
+    })
+}
+
+// The same file parsed through another profile gives other rows, so it counts as a new upload
+fn upload_key(content_hash: &str, profile_id: Option<&str>) -> String {
+    match profile_id {
+        Some(profile_id) => format!("{}:{}", content_hash, profile_id),
+        None => content_hash.to_string(),
+    }
+}
+
+fn batch_for_upload(key: &str) -> Option<ProcessedData> {
+    UPLOAD_HASHES.with(|hashes| {
+        let batch_id = hashes.borrow().get(key)?.clone();
+        BATCHES.with(|batches| batches.borrow().get(&batch_id).map(summarize))
+    })
+}
+
+/// What `register_batch` did with an upload.
+enum Registered {
+    New(String),
+    /// The same upload was registered before; this is the status of its batch.
+    Existing(ProcessedData),
+}
+
+/// Stores a new `Pending` batch, or a `Draft` for manual entry, unless it repeats an earlier upload.
+fn register_batch(new: NewBatch) -> Result<Registered, AggregatorError> {
+    let key = new
+        .content_hash
+        .as_deref()
+        .map(|content_hash| upload_key(content_hash, new.profile_id.as_deref()));
+    if let Some(existing) = key.as_deref().and_then(batch_for_upload) {
+        return Ok(Registered::Existing(existing));
+    }
+    prune_batches(new.data.len())?;
+
+    let batch_id = generate_batch_id();
+    if let Some(key) = key {
+        UPLOAD_HASHES.with(|hashes| hashes.borrow_mut().insert(key, batch_id.clone()));
+    }
+    let batch = DataBatch {
+        batch_id: batch_id.clone(),
+        source: new.source,
+        data: new.data,
+        status: if new.manual.is_some() {
+            ProcessingStatus::Draft
+        } else {
+            ProcessingStatus::Pending
+        },
+        created_at: ic_cdk::api::time(),
+        processed_at: None,
+        record_errors: new.parse_errors,
+        content_hash: new.content_hash,
+        profile_id: new.profile_id,
+        manual: new.manual,
+        processing_nanos: None,
+    };
+    BATCHES.with(|batches| {
+        batches.borrow_mut().insert(batch_id.clone(), batch);
+    });
+    Ok(Registered::New(batch_id))
+}
+
+/// Whether the batch is done with, so it may be left out of snapshots or pruned.
+fn is_settled(batch: &DataBatch) -> bool {
+    matches!(
+        batch.status,
+        ProcessingStatus::Completed
+            | ProcessingStatus::PartiallyCompleted
+            | ProcessingStatus::Failed
+            | ProcessingStatus::Rejected
+    )
+}
+
+// Drops settled batches past their retention, then the oldest settled ones until `incoming` more
+// records fit. Batches still pending, processing or in review are never dropped.
+fn prune_batches(incoming: usize) -> Result<(), AggregatorError> {
+    let now = ic_cdk::api::time();
+    let stored = BATCHES.with(|batches| {
+        let mut batches = batches.borrow_mut();
+        let mut settled: Vec<(u64, String)> = batches
+            .values()
+            .filter(|batch| is_settled(batch))
+            .map(|batch| (batch.processed_at.unwrap_or(batch.created_at), batch.batch_id.clone()))
+            .collect();
+        settled.sort();
+
+        let mut stored: usize = batches.values().map(|batch| batch.data.len()).sum();
+        for (settled_at, batch_id) in settled {
+            let expired = now.saturating_sub(settled_at) > BATCH_RETENTION_NANOS;
+            if !expired && stored + incoming <= MAX_STORED_RECORDS {
+                break;
             }
-            Err(e) => {
-                update_processing_stats(false, batch.data.len());
-                Err(format!("Failed to update inventory: {}", e))
+            if let Some(batch) = batches.remove(&batch_id) {
+                stored -= batch.data.len();
             }
         }
+        // Re-uploading a pruned batch's file imports it again
+        UPLOAD_HASHES.with(|hashes| hashes.borrow_mut().retain(|_, batch_id| batches.contains_key(batch_id)));
+        stored
+    });
+
+    if stored + incoming > MAX_STORED_RECORDS {
+        return Err(AggregatorError::InvalidUpload {
+            reason: format!(
+                "{} records can't be stored: {} are held by batches awaiting processing or review, out of {}",
+                incoming, stored, MAX_STORED_RECORDS
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Processes a `Pending` batch; use `retry_failed_batch` or `resubmit_records` once it has run. Reviewers only.
+#[update]
+async fn process_batch(batch_id: String) -> Result<ProcessedData, AggregatorError> {
+    require_reviewer()?;
+    process_pending(batch_id).await
+}
+
+// Imports a `Pending` batch for uploads, integrations and approvals, which check their callers themselves
+async fn process_pending(batch_id: String) -> Result<ProcessedData, AggregatorError> {
+    let rows = with_batch(&batch_id, |batch| {
+        if batch.status != ProcessingStatus::Pending {
+            return Err(AggregatorError::invalid_batch_state(batch));
+        }
+        // Rows that failed to parse stay quarantined until resubmitted
+        let unparsed: HashSet<usize> = quarantined_rows(batch).into_iter().collect();
+        Ok((0..batch.data.len()).filter(|row| !unparsed.contains(row)).collect())
+    })?;
+    process_records(&batch_id, rows).await
+}
+
+/// Processes the quarantined records of a `Failed` or `PartiallyCompleted` batch again, unchanged;
+/// useful once rules, categories or inventory have been fixed, or after a failed inter-canister call.
+/// Reviewers only.
+#[update]
+async fn retry_failed_batch(batch_id: String) -> Result<ProcessedData, AggregatorError> {
+    require_reviewer()?;
+    let rows = with_batch(&batch_id, |batch| {
+        if !matches!(batch.status, ProcessingStatus::Failed | ProcessingStatus::PartiallyCompleted) {
+            return Err(AggregatorError::invalid_batch_state(batch));
+        }
+        Ok(quarantined_rows(batch))
+    })?;
+    process_records(&batch_id, rows).await
+}
+
+/// Replaces quarantined records with corrected versions and processes only those rows again; reviewers only.
+/// Corrections to a manual batch aren't imported here: they go into a new draft, returned instead, which
+/// needs a second reviewer's approval like any other manual entry.
+#[update]
+async fn resubmit_records(batch_id: String, records: Vec<CorrectedRecord>) -> Result<ProcessedData, AggregatorError> {
+    let editor = require_reviewer()?;
+    let is_manual = with_batch(&batch_id, |batch| {
+        // Drafts are edited with `update_manual_batch` until approved
+        if matches!(
+            batch.status,
+            ProcessingStatus::Draft | ProcessingStatus::Processing | ProcessingStatus::Rejected
+        ) {
+            return Err(AggregatorError::invalid_batch_state(batch));
+        }
+        if records.is_empty() {
+            return Err(AggregatorError::InvalidUpload {
+                reason: "No records to resubmit".to_string(),
+            });
+        }
+        let quarantined: HashSet<u32> = batch.record_errors.iter().map(|e| e.row_index).collect();
+        if let Some(record) = records.iter().find(|r| !quarantined.contains(&r.row_index)) {
+            return Err(AggregatorError::InvalidUpload {
+                reason: format!("Row {} is not quarantined", record.row_index),
+            });
+        }
+        Ok(batch.manual.is_some())
+    })?;
+    if is_manual {
+        return resubmit_for_review(&batch_id, records, editor);
+    }
+
+    let rows = with_batch(&batch_id, |batch| {
+        let mut rows = Vec::with_capacity(records.len());
+        for record in records {
+            rows.push(record.row_index as usize);
+            batch.data[record.row_index as usize] = record.item;
+        }
+        rows.sort_unstable();
+        rows.dedup();
+        Ok(rows)
+    })?;
+    process_records(&batch_id, rows).await
+}
+
+// Puts corrected rows of a manual batch into a new draft authored by `editor`; the original rows stay
+// quarantined, pointing at the draft
+fn resubmit_for_review(
+    batch_id: &str,
+    records: Vec<CorrectedRecord>,
+    editor: Principal,
+) -> Result<ProcessedData, AggregatorError> {
+    // The last correction of a row wins, as when they are written into the batch
+    let corrected: BTreeMap<u32, InventoryItemInput> =
+        records.into_iter().map(|record| (record.row_index, record.item)).collect();
+    let data: Vec<InventoryItemInput> = corrected.values().cloned().collect();
+    check_manual_records(&data)?;
+
+    let draft = NewBatch {
+        source: DataSource::Manual,
+        data,
+        parse_errors: Vec::new(),
+        content_hash: None,
+        profile_id: None,
+        manual: Some(ManualEntry {
+            submitted_by: editor,
+            note: format!("Corrections to quarantined rows of {}", batch_id),
+            last_edited_by: editor,
+            reviewed_by: None,
+            review_comment: None,
+            ledger_transaction_id: None,
+        }),
+    };
+    let draft_id = match register_batch(draft)? {
+        Registered::New(draft_id) => draft_id,
+        Registered::Existing(existing) => existing.batch_id,
+    };
+    with_batch(batch_id, |batch| {
+        for error in batch.record_errors.iter_mut().filter(|e| corrected.contains_key(&e.row_index)) {
+            error.message = format!("Resubmitted for review in {}: {}", draft_id, error.message);
+        }
+        Ok(())
+    })?;
+    with_batch(&draft_id, |draft| Ok(summarize(draft)))
+}
+
+fn with_batch<T>(
+    batch_id: &str,
+    f: impl FnOnce(&mut DataBatch) -> Result<T, AggregatorError>,
+) -> Result<T, AggregatorError> {
+    BATCHES.with(|batches| {
+        let mut batches = batches.borrow_mut();
+        let batch = batches
+            .get_mut(batch_id)
+            .ok_or_else(|| AggregatorError::BatchNotFound { batch_id: batch_id.to_string() })?;
+        f(batch)
+    })
+}
+
+fn quarantined_rows(batch: &DataBatch) -> Vec<usize> {
+    let mut rows: Vec<usize> = batch.record_errors.iter().map(|e| e.row_index as usize).collect();
+    rows.dedup(); // record_errors is kept sorted by row
+    rows
+}
+
+// Imports the valid records among `rows` and quarantines the rest; other rows keep their earlier outcome.
+// If a call to inventory fails, the rows it didn't settle are quarantined with the error for a retry to pick up.
+async fn process_records(batch_id: &str, rows: Vec<usize>) -> Result<ProcessedData, AggregatorError> {
+    let started_at = ic_cdk::api::time();
+    // Marking the batch Processing keeps concurrent calls from working on it across the awaits below
+    let mut data = with_batch(batch_id, |batch| {
+        if batch.status == ProcessingStatus::Processing {
+            return Err(AggregatorError::invalid_batch_state(batch));
+        }
+        batch.status = ProcessingStatus::Processing;
+        Ok(batch.data.clone())
+    })?;
+
+    let mut errors = Vec::new();
+    let mut imported = HashSet::new();
+    let outcome = import_records(batch_id, &mut data, &rows, &mut errors, &mut imported).await;
+    if let Err(error) = &outcome {
+        let settled: HashSet<usize> = errors.iter().map(|e| e.row_index as usize).chain(imported).collect();
+        for &row in rows.iter().filter(|row| !settled.contains(row)) {
+            errors.push(RecordError {
+                row_index: row as u32,
+                item_id: data[row].item_id.clone(),
+                rule_id: None,
+                message: format!("Not processed: {:?}", error),
+            });
+        }
+    }
+
+    let processed: HashSet<u32> = rows.iter().map(|row| *row as u32).collect();
+    let processed_data = with_batch(batch_id, |batch| {
+        batch.data = data;
+        batch.record_errors.retain(|e| !processed.contains(&e.row_index));
+        batch.record_errors.extend(errors);
+        batch.record_errors.sort_by_key(|e| e.row_index);
+        let processed_at = ic_cdk::api::time();
+        batch.processed_at = Some(processed_at);
+        batch.processing_nanos = Some(batch.processing_nanos.unwrap_or_default() + (processed_at - started_at));
+
+        batch.status = settled_status(batch);
+        Ok(summarize(batch))
+    })?;
+
+    outcome.map(|_| processed_data)
+}
+
+async fn import_records(
+    batch_id: &str,
+    items: &mut [InventoryItemInput],
+    rows: &[usize],
+    errors: &mut Vec<RecordError>,
+    imported: &mut HashSet<usize>,
+) -> Result<(), AggregatorError> {
+    // Map imported category labels onto inventory categories
+    errors.extend(resolve_categories(items, rows).await?);
+
+    // Validate data
+    errors.extend(validate_records(items, rows));
+
+    // Import the rows that passed
+    let quarantined: HashSet<u32> = errors.iter().map(|e| e.row_index).collect();
+    let valid_rows: Vec<usize> = rows
+        .iter()
+        .copied()
+        .filter(|row| !quarantined.contains(&(*row as u32)))
+        .collect();
+    update_inventory(batch_id, items, &valid_rows, errors, imported).await
+}
+
+fn quarantined_count(batch: &DataBatch) -> u32 {
+    batch
+        .record_errors
+        .iter()
+        .map(|e| e.row_index)
+        .collect::<HashSet<_>>()
+        .len() as u32
+}
+
+// Status of a batch once a processing run has finished
+fn settled_status(batch: &DataBatch) -> ProcessingStatus {
+    let error_count = quarantined_count(batch);
+    if error_count == 0 {
+        ProcessingStatus::Completed
+    } else if error_count == batch.data.len() as u32 {
+        ProcessingStatus::Failed
     } else {
-        update_processing_stats(false, batch.data.len());
-        Err(format!("Validation failed: {:?}", validation_results))
+        ProcessingStatus::PartiallyCompleted
+    }
+}
+
+fn summarize(batch: &DataBatch) -> ProcessedData {
+    let records_count = batch.data.len() as u32;
+    let error_count = quarantined_count(batch);
+    let success_count = records_count - error_count;
+
+    ProcessedData {
+        batch_id: batch.batch_id.clone(),
+        source: batch.source,
+        timestamp: batch.processed_at.unwrap_or(batch.created_at),
+        status: batch.status,
+        records_count,
+        success_count,
+        error_count,
+        validation_errors: batch.record_errors.clone(),
     }
 }
 
 // Helper functions
+fn require_controller() -> Result<(), AggregatorError> {
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err(AggregatorError::Unauthorized { caller })
+    }
+}
+
+fn inventory_canister_id() -> Result<Principal, AggregatorError> {
+    DEPENDENCIES
+        .with(|deps| deps.borrow().inventory)
+        .ok_or_else(|| AggregatorError::dependency_not_configured("inventory"))
+}
+
+fn ledger_canister_id() -> Result<Principal, AggregatorError> {
+    DEPENDENCIES
+        .with(|deps| deps.borrow().ledger)
+        .ok_or_else(|| AggregatorError::dependency_not_configured("ledger"))
+}
+
+fn require_reviewer() -> Result<Principal, AggregatorError> {
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) || REVIEWERS.with(|reviewers| reviewers.borrow().contains(&caller)) {
+        Ok(caller)
+    } else {
+        Err(AggregatorError::Unauthorized { caller })
+    }
+}
+
+// Uploads buffer up to `MAX_UPLOAD_SIZE` per session, so they aren't open to just anyone
+fn require_uploader() -> Result<Principal, AggregatorError> {
+    let caller = ic_cdk::caller();
+    let is_integration = INTEGRATIONS.with(|integrations| {
+        integrations
+            .borrow()
+            .values()
+            .any(|integration| integration.principal == Some(caller))
+    });
+    if caller != Principal::anonymous() && (require_reviewer().is_ok() || is_integration) {
+        Ok(caller)
+    } else {
+        Err(AggregatorError::Unauthorized { caller })
+    }
+}
+
 fn generate_batch_id() -> String {
-    format!("BATCH_{}", ic_cdk::api::time())
+    generate_id("BATCH")
+}
+
+// time() is the same for every message in a round, so the counter keeps IDs apart
+fn generate_id(prefix: &str) -> String {
+    let sequence = BATCH_COUNTER.with(|counter| {
+        let next = counter.get() + 1;
+        counter.set(next);
+        next
+    });
+    format!("{}_{}_{}", prefix, ic_cdk::api::time(), sequence)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
 }
 
-async fn process_excel_data(excel_data: &[u8]) -> Result<Vec<InventoryItem>, String> {
+async fn process_excel_data(excel_data: &[u8]) -> Result<Vec<InventoryItemInput>, AggregatorError> {
     if excel_data.is_empty() {
-        return Err("Empty Excel data provided".to_string());
+        return Err(AggregatorError::InvalidUpload {
+            reason: "Empty Excel data provided".to_string(),
+        });
     }
 
     // Basic implementation for Excel processing
-    let mut items = Vec::new();
-    
     // TODO: Implement actual Excel parsing using the excel_data
     // For now, we'll create a sample item
-    items.push(InventoryItem {
+    let items = vec![InventoryItemInput {
         item_id: "TEST_001".to_string(),
         barcode: "123456789".to_string(),
         name: "Test Item".to_string(),
         category: Some("Test Category".to_string()),
-        quantity: 10,
-        expiration_date: ic_cdk::api::time() + 86400_000, // 24 hours from now
-        price: 9.99,
-    });
+        quantity: Quantity::whole(10, UnitOfMeasure::Each),
+        expiration_date: ic_cdk::api::time() + 24 * 60 * 60 * 1_000_000_000, // 24 hours from now
+        price: Money::new(999, DEFAULT_CURRENCY),
+    }];
 
     Ok(items)
 }
 
-fn validate_batch(batch: &DataBatch) -> Vec<String> {
+/// Replaces category labels in `rows` with inventory category IDs, returning an error per unknown label.
+async fn resolve_categories(items: &mut [InventoryItemInput], rows: &[usize]) -> Result<Vec<RecordError>, AggregatorError> {
+    let labelled: Vec<usize> = rows.iter().copied().filter(|row| items[*row].category.is_some()).collect();
+    if labelled.is_empty() {
+        return Ok(Vec::new());
+    }
+    let labels: Vec<String> = labelled.iter().filter_map(|row| items[*row].category.clone()).collect();
+
+    let inventory = inventory_canister_id()?;
+    let (resolved,): (Vec<Option<String>>,) =
+        ic_cdk::api::call::call(inventory, "resolve_categories", (labels,))
+            .await
+            .map_err(|e| CallFailure::new(inventory, "resolve_categories", e))?;
+
+    let mut errors = Vec::new();
+    for (row, category_id) in labelled.into_iter().zip(resolved) {
+        let item = &mut items[row];
+        match category_id {
+            Some(category_id) => item.category = Some(category_id),
+            None => errors.push(RecordError {
+                row_index: row as u32,
+                item_id: item.item_id.clone(),
+                rule_id: None,
+                message: format!("Unknown category '{}'", item.category.as_deref().unwrap_or_default()),
+            }),
+        }
+    }
+
+    Ok(errors)
+}
+
+fn validate_records(items: &[InventoryItemInput], rows: &[usize]) -> Vec<RecordError> {
     let mut errors = Vec::new();
     
     VALIDATION_RULES.with(|rules| {
-        for item in &batch.data {
-            for rule in rules.borrow().values() {
-                if !validate_item(item, rule) {
-                    errors.push(rule.error_message.clone());
-                }
+        let rules = rules.borrow();
+        let rule_set = RuleSet::new(rules.values(), ic_cdk::api::time());
+        for &row in rows {
+            let item = &items[row];
+            for rule in rule_set.failures(item) {
+                errors.push(RecordError {
+                    row_index: row as u32,
+                    item_id: item.item_id.clone(),
+                    rule_id: Some(rule.rule_id.clone()),
+                    message: rule.error_message.clone(),
+                });
             }
         }
     });
@@ -196,53 +1000,255 @@ fn validate_batch(batch: &DataBatch) -> Vec<String> {
     errors
 }
 
-fn validate_item(item: &InventoryItem, rule: &ValidationRule) -> bool {
-    match rule.field.as_str() {
-        "name" => !item.name.is_empty(),
-        "quantity" => item.quantity > 0,
-        "price" => item.price > 0.0,
-        _ => true,
+// Moves the movements failing a validation rule to the errors, so they aren't applied
+fn validate_movements((movements, mut errors): ParsedMovements) -> ParsedMovements {
+    let mut valid = Vec::with_capacity(movements.len());
+    VALIDATION_RULES.with(|rules| {
+        let rules = rules.borrow();
+        let rule_set = RuleSet::new(rules.values(), ic_cdk::api::time());
+        for (row, movement) in movements {
+            let failures = rule_set.movement_failures(&movement);
+            if failures.is_empty() {
+                valid.push((row, movement));
+                continue;
+            }
+            errors.extend(failures.into_iter().map(|rule| RecordError {
+                row_index: row,
+                item_id: movement.item_id().to_string(),
+                rule_id: Some(rule.rule_id.clone()),
+                message: rule.error_message.clone(),
+            }));
+        }
+    });
+    (valid, errors)
+}
+
+/// Adds or updates the items in `rows` through inventory's `import_items`, recording the rows it
+/// rejected in `errors` and the rest in `imported`.
+async fn update_inventory(
+    batch_id: &str,
+    items: &[InventoryItemInput],
+    rows: &[usize],
+    errors: &mut Vec<RecordError>,
+    imported: &mut HashSet<usize>,
+) -> Result<(), AggregatorError> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    ic_cdk::println!("Updating inventory with {} items from batch {}", rows.len(), batch_id);
+
+    let inventory = inventory_canister_id()?;
+    // Chunked to stay under the inter-canister message size limit
+    for chunk in rows.chunks(IMPORT_CHUNK_SIZE) {
+        let results = import_items(inventory, chunk.iter().map(|row| &items[*row]).collect()).await?;
+
+        for (&row, result) in chunk.iter().zip(results) {
+            match result {
+                Ok(_) => {
+                    imported.insert(row);
+                }
+                Err(error) => errors.push(RecordError {
+                    row_index: row as u32,
+                    item_id: items[row].item_id.clone(),
+                    rule_id: None,
+                    message: format!("Rejected by inventory: {:?}", error),
+                }),
+            }
+        }
     }
+
+    Ok(())
 }
 
-async fn update_inventory(batch: &DataBatch) -> Result<(), String> {
-    let items_count = batch.data.len();
-    
-    // TODO: Implement actual inventory update
-    // For now, we'll just log the update
-    ic_cdk::println!(
-        "Updating inventory with {} items from batch {}",
-        items_count,
-        batch.batch_id
-    );
-
-    // Simulate some processing time
-    // In a real implementation, this would be an inter-canister call
-    if items_count > 0 {
-        Ok(())
-    } else {
-        Err("No items to update".to_string())
+/// One call to inventory's `import_items`; callers keep `items` under `IMPORT_CHUNK_SIZE`.
+async fn import_items(
+    inventory: Principal,
+    items: Vec<&InventoryItemInput>,
+) -> Result<Vec<Result<String, InventoryError>>, AggregatorError> {
+    let (results,): (Result<Vec<Result<String, InventoryError>>, InventoryError>,) =
+        ic_cdk::api::call::call(inventory, "import_items", (items,))
+            .await
+            .map_err(|e| CallFailure::new(inventory, "import_items", e))?;
+    // Only an unauthorized caller fails the whole call, e.g. when inventory doesn't know this canister yet
+    results.map_err(|error| AggregatorError::InventoryUpdateFailed {
+        reason: format!("{:?}", error),
+    })
+}
+
+// Query methods
+/// Statistics over the batches settled in the last `window_days` days, or since the last reset when omitted.
+/// The daily series covers the same days, or the last 30 without a window.
+#[query]
+fn get_processing_statistics(window_days: Option<u32>) -> ProcessingStatistics {
+    let now = ic_cdk::api::time();
+    let reset_at = STATS_RESET_AT.with(|reset_at| reset_at.get());
+    let days = window_days.unwrap_or(stats::DEFAULT_SERIES_DAYS).clamp(1, stats::MAX_SERIES_DAYS);
+    let since = match window_days {
+        Some(_) => reset_at.max(now.saturating_sub(days as u64 * stats::NANOS_PER_DAY)),
+        None => reset_at,
+    };
+    BATCHES.with(|batches| stats::compute(batches.borrow().values(), since, now, days))
+}
+
+/// Starts the statistics afresh; batches settled earlier are no longer counted.
+#[update]
+fn reset_processing_statistics() -> Result<String, AggregatorError> {
+    require_controller()?;
+    STATS_RESET_AT.with(|reset_at| reset_at.set(ic_cdk::api::time()));
+    Ok("Processing statistics reset".to_string())
+}
+
+/// Saves stock counts typed in by staff as a draft batch; nothing is applied until a reviewer approves it.
+#[update]
+fn create_manual_batch(records: Vec<InventoryItemInput>, note: String) -> Result<String, AggregatorError> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(AggregatorError::Unauthorized { caller });
+    }
+    check_manual_records(&records)?;
+
+    let batch = NewBatch {
+        source: DataSource::Manual,
+        data: records,
+        parse_errors: Vec::new(),
+        content_hash: None,
+        profile_id: None,
+        manual: Some(ManualEntry {
+            submitted_by: caller,
+            note,
+            last_edited_by: caller,
+            reviewed_by: None,
+            review_comment: None,
+            ledger_transaction_id: None,
+        }),
+    };
+    // Without a content hash there is nothing to dedupe against
+    match register_batch(batch)? {
+        Registered::New(batch_id) => Ok(batch_id),
+        Registered::Existing(existing) => Ok(existing.batch_id),
     }
 }
 
-fn update_processing_stats(success: bool, record_count: usize) {
-    PROCESSING_STATS.with(|stats| {
-        let mut stats = stats.borrow_mut();
-        stats.total_batches += 1;
-        stats.total_records += record_count as u64;
-        if success {
-            stats.successful_batches += 1;
-        } else {
-            stats.failed_batches += 1;
+/// Replaces the records of a draft; open to its author and to reviewers.
+#[update]
+fn update_manual_batch(batch_id: String, records: Vec<InventoryItemInput>) -> Result<String, AggregatorError> {
+    let caller = ic_cdk::caller();
+    let is_reviewer = require_reviewer().is_ok();
+    check_manual_records(&records)?;
+    with_batch(&batch_id, |batch| {
+        let Some(entry) = batch.manual.as_mut().filter(|_| batch.status == ProcessingStatus::Draft) else {
+            return Err(AggregatorError::invalid_batch_state(batch));
+        };
+        if entry.submitted_by != caller && !is_reviewer {
+            return Err(AggregatorError::Unauthorized { caller });
         }
-        stats.success_rate = (stats.successful_batches as f64 / stats.total_batches as f64) * 100.0;
-    });
+        entry.last_edited_by = caller;
+        batch.data = records;
+        Ok(format!("Manual batch {} updated", batch_id))
+    })
 }
 
-// Query methods
+/// Approves a draft, recording the approval in the ledger before the records are imported.
+#[update]
+async fn approve_manual_batch(batch_id: String, comment: String) -> Result<ProcessedData, AggregatorError> {
+    review_manual_batch(&batch_id, true, comment).await?;
+    process_pending(batch_id).await
+}
+
+/// Turns down a draft; the rejection is recorded in the ledger and nothing is imported.
+#[update]
+async fn reject_manual_batch(batch_id: String, comment: String) -> Result<String, AggregatorError> {
+    review_manual_batch(&batch_id, false, comment).await?;
+    Ok(format!("Manual batch {} rejected", batch_id))
+}
+
+fn check_manual_records(records: &[InventoryItemInput]) -> Result<(), AggregatorError> {
+    if records.is_empty() || records.len() > manual::MAX_MANUAL_RECORDS {
+        return Err(AggregatorError::InvalidUpload {
+            reason: format!("Manual batches hold between 1 and {} records", manual::MAX_MANUAL_RECORDS),
+        });
+    }
+    Ok(())
+}
+
+// Leaves an approved batch `Pending` and a rejected one `Rejected`, once the ledger has the decision
+async fn review_manual_batch(batch_id: &str, approved: bool, comment: String) -> Result<(), AggregatorError> {
+    let reviewer = require_reviewer()?;
+    // Checked up front so a missing ledger leaves the draft untouched
+    ledger_canister_id()?;
+    let (kind, records) = with_batch(batch_id, |batch| {
+        let Some(entry) = batch.manual.as_ref().filter(|_| batch.status == ProcessingStatus::Draft) else {
+            return Err(AggregatorError::invalid_batch_state(batch));
+        };
+        if entry.submitted_by == reviewer || entry.last_edited_by == reviewer {
+            return Err(AggregatorError::SecondReviewerRequired { batch_id: batch_id.to_string() });
+        }
+        let kind = TransactionKind::ManualEntryReview {
+            batch_id: batch_id.to_string(),
+            submitted_by: entry.submitted_by.to_text(),
+            approved,
+            item_ids: batch.data.iter().map(|item| item.item_id.clone()).collect(),
+            comment: comment.clone(),
+        };
+        // Held while the ledger call is in flight so the draft can't be edited or reviewed twice
+        batch.status = ProcessingStatus::Processing;
+        Ok((kind, batch.data.clone()))
+    })?;
+
+    let recorded = manual::record_review(kind, reviewer, batch_id, &records).await;
+    with_batch(batch_id, |batch| {
+        batch.status = match &recorded {
+            Ok(_) if approved => ProcessingStatus::Pending,
+            Ok(_) => ProcessingStatus::Rejected,
+            Err(_) => ProcessingStatus::Draft,
+        };
+        if let (Ok(transaction_id), Some(entry)) = (&recorded, batch.manual.as_mut()) {
+            entry.reviewed_by = Some(reviewer);
+            entry.review_comment = Some(comment);
+            entry.ledger_transaction_id = Some(*transaction_id);
+        }
+        Ok(())
+    })?;
+    recorded.map(|_| ())
+}
+
+#[query]
+fn get_batch_status(batch_id: String) -> Result<ProcessedData, AggregatorError> {
+    BATCHES.with(|batches| {
+        batches
+            .borrow()
+            .get(&batch_id)
+            .map(summarize)
+            .ok_or(AggregatorError::BatchNotFound { batch_id })
+    })
+}
+
+/// Batches newest first, skipping `offset` and returning at most `limit` (capped at 100).
 #[query]
-fn get_processing_statistics() -> ProcessingStatistics {
-    PROCESSING_STATS.with(|stats| stats.borrow().clone())
+fn list_batches(offset: u64, limit: u64) -> Vec<DataBatch> {
+    BATCHES.with(|batches| {
+        let batches = batches.borrow();
+        let mut listed: Vec<&DataBatch> = batches.values().collect();
+        listed.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.batch_id.cmp(&a.batch_id)));
+        listed
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_BATCHES_PER_PAGE) as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+/// Per-record errors of the batch's quarantined rows, ordered by row.
+#[query]
+fn get_batch_errors(batch_id: String) -> Result<Vec<RecordError>, AggregatorError> {
+    BATCHES.with(|batches| {
+        batches
+            .borrow()
+            .get(&batch_id)
+            .map(|batch| batch.record_errors.clone())
+            .ok_or(AggregatorError::BatchNotFound { batch_id })
+    })
 }
 
 #[query]
@@ -250,15 +1256,260 @@ fn get_validation_rules() -> Vec<ValidationRule> {
     VALIDATION_RULES.with(|rules| rules.borrow().values().cloned().collect())
 }
 
+#[query]
+fn get_sync_status() -> SyncStatus {
+    SYNC_STATUS.with(|status| status.borrow().clone())
+}
+
+#[query]
+fn list_mapping_profiles() -> Vec<MappingProfile> {
+    MAPPING_PROFILES.with(|profiles| profiles.borrow().values().cloned().collect())
+}
+
+/// Parses the first rows of `data` through `profile`, saved or not, without importing anything.
+#[query]
+fn preview_mapping(data: Vec<u8>, profile: MappingProfile) -> Result<MappingPreview, AggregatorError> {
+    mapping::check_profile(&profile).map_err(|reason| AggregatorError::InvalidProfile {
+        profile_id: profile.profile_id.clone(),
+        reason,
+    })?;
+    mapping::preview(&data, &profile, PREVIEW_ROWS).map_err(|reason| AggregatorError::InvalidUpload { reason })
+}
+
+#[query]
+fn get_dependencies() -> Dependencies {
+    DEPENDENCIES.with(|deps| deps.borrow().clone())
+}
+
+// Admin methods
+#[update]
+fn add_validation_rule(rule: ValidationRule) -> Result<String, AggregatorError> {
+    require_controller()?;
+    validation::check_rule(&rule).map_err(|reason| AggregatorError::InvalidRule {
+        rule_id: rule.rule_id.clone(),
+        reason,
+    })?;
+
+    let rule_id = rule.rule_id.clone();
+    let replaced = VALIDATION_RULES.with(|rules| rules.borrow_mut().insert(rule_id.clone(), rule));
+    Ok(match replaced {
+        Some(_) => format!("Validation rule {} updated", rule_id),
+        None => format!("Validation rule {} added", rule_id),
+    })
+}
+
+#[update]
+fn remove_validation_rule(rule_id: String) -> Result<String, AggregatorError> {
+    require_controller()?;
+    VALIDATION_RULES
+        .with(|rules| rules.borrow_mut().remove(&rule_id))
+        .map(|_| format!("Validation rule {} removed", rule_id))
+        .ok_or(AggregatorError::RuleNotFound { rule_id })
+}
+
+/// Compares inventory against the latest import of each item, reporting missing items and quantity
+/// or price drift; with `fix`, the imported records are written back to inventory.
+#[update]
+async fn sync_with_inventory(fix: bool) -> Result<SyncReport, AggregatorError> {
+    require_controller()?;
+    let inventory = inventory_canister_id()?;
+    if SYNC_STATUS.with(|status| std::mem::replace(&mut status.borrow_mut().running, true)) {
+        return Err(AggregatorError::SyncInProgress);
+    }
+
+    let result = sync::reconcile(inventory, fix).await;
+    SYNC_STATUS.with(|status| {
+        let mut status = status.borrow_mut();
+        status.running = false;
+        match &result {
+            Ok(report) => {
+                status.last_sync = Some(report.completed_at);
+                status.last_error = None;
+                status.report = Some(report.clone());
+            }
+            Err(error) => status.last_error = Some(format!("{:?}", error)),
+        }
+    });
+    result
+}
+
+#[update]
+fn save_mapping_profile(profile: MappingProfile) -> Result<String, AggregatorError> {
+    require_controller()?;
+    mapping::check_profile(&profile).map_err(|reason| AggregatorError::InvalidProfile {
+        profile_id: profile.profile_id.clone(),
+        reason,
+    })?;
+
+    let profile_id = profile.profile_id.clone();
+    let replaced = MAPPING_PROFILES.with(|profiles| profiles.borrow_mut().insert(profile_id.clone(), profile));
+    Ok(match replaced {
+        Some(_) => format!("Mapping profile {} updated", profile_id),
+        None => format!("Mapping profile {} added", profile_id),
+    })
+}
+
+#[update]
+fn remove_mapping_profile(profile_id: String) -> Result<String, AggregatorError> {
+    require_controller()?;
+    MAPPING_PROFILES
+        .with(|profiles| profiles.borrow_mut().remove(&profile_id))
+        .map(|_| format!("Mapping profile {} removed", profile_id))
+        .ok_or(AggregatorError::ProfileNotFound { profile_id })
+}
+
+#[update]
+fn register_integration(integration: Integration) -> Result<String, AggregatorError> {
+    require_controller()?;
+    api::check_integration(&integration).map_err(|reason| AggregatorError::InvalidIntegration {
+        integration_id: integration.integration_id.clone(),
+        reason,
+    })?;
+
+    let integration_id = integration.integration_id.clone();
+    let replaced =
+        INTEGRATIONS.with(|integrations| integrations.borrow_mut().insert(integration_id.clone(), integration));
+    Ok(match replaced {
+        Some(_) => format!("Integration {} updated", integration_id),
+        None => format!("Integration {} registered", integration_id),
+    })
+}
+
+#[update]
+fn remove_integration(integration_id: String) -> Result<String, AggregatorError> {
+    require_controller()?;
+    INTEGRATIONS
+        .with(|integrations| integrations.borrow_mut().remove(&integration_id))
+        .map(|_| format!("Integration {} removed", integration_id))
+        .ok_or(AggregatorError::IntegrationNotFound { integration_id })
+}
+
+#[query]
+fn list_integrations() -> Result<Vec<IntegrationInfo>, AggregatorError> {
+    require_controller()?;
+    Ok(INTEGRATIONS.with(|integrations| integrations.borrow().values().map(IntegrationInfo::from).collect()))
+}
+
+#[query]
+fn get_reviewers() -> Vec<Principal> {
+    REVIEWERS.with(|reviewers| reviewers.borrow().clone())
+}
+
+/// Sets who, besides controllers, may approve or reject manual batches.
+#[update]
+fn set_reviewers(reviewers: Vec<Principal>) -> Result<String, AggregatorError> {
+    require_controller()?;
+    REVIEWERS.with(|stored| *stored.borrow_mut() = reviewers);
+    Ok("Reviewers updated".to_string())
+}
+
+#[update]
+fn set_dependencies(dependencies: Dependencies) -> Result<String, AggregatorError> {
+    require_controller()?;
+    DEPENDENCIES.with(|deps| *deps.borrow_mut() = dependencies);
+    Ok("Dependencies updated".to_string())
+}
+
 // State management
+/// Layout saved by `pre_upgrade`: dependencies, validation rules, batches, batch counter, mapping profiles,
+/// sync status, integrations, reviewers, the statistics reset time and the movement reports.
+type StableState = (
+    Dependencies,
+    Option<HashMap<String, ValidationRule>>,
+    Option<HashMap<String, DataBatch>>,
+    Option<u64>,
+    Option<HashMap<String, MappingProfile>>,
+    Option<SyncStatus>,
+    Option<HashMap<String, Integration>>,
+    Option<Vec<Principal>>,
+    Option<u64>,
+    Option<HashMap<String, MovementReport>>,
+);
+
+// State added after the first release is saved as `opt`, so state written by older versions
+// (which lacks those trailing values) still decodes, with `None` for what it didn't save.
 #[pre_upgrade]
 fn pre_upgrade() {
-    // Implement state saving logic here
+    // Moved out rather than cloned; a failed upgrade rolls this back along with everything else
+    let dependencies = DEPENDENCIES.with(|deps| deps.take());
+    let rules = VALIDATION_RULES.with(|rules| rules.take());
+    let batches = BATCHES.with(|batches| batches.take());
+    let counter = BATCH_COUNTER.with(|counter| counter.get());
+    let profiles = MAPPING_PROFILES.with(|profiles| profiles.take());
+    let sync_status = SYNC_STATUS.with(|status| status.take());
+    let integrations = INTEGRATIONS.with(|integrations| integrations.take());
+    let reviewers = REVIEWERS.with(|reviewers| reviewers.take());
+    let stats_reset_at = STATS_RESET_AT.with(|reset_at| reset_at.get());
+    let movement_reports = MOVEMENT_REPORTS.with(|reports| reports.take());
+    ic_cdk::storage::stable_save((
+        dependencies,
+        Some(rules),
+        Some(batches),
+        Some(counter),
+        Some(profiles),
+        Some(sync_status),
+        Some(integrations),
+        Some(reviewers),
+        Some(stats_reset_at),
+        Some(movement_reports),
+    ))
+    .unwrap();
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    // Implement state restoration logic here
+    // The first release saved nothing, so upgrading from it finds stable memory empty
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        return;
+    }
+    // Anything else that doesn't decode fails the upgrade rather than wiping the batches and settings
+    let (
+        dependencies,
+        rules,
+        stored_batches,
+        counter,
+        profiles,
+        sync_status,
+        integrations,
+        reviewers,
+        stats_reset_at,
+        movement_reports,
+    ): StableState = ic_cdk::storage::stable_restore()
+        .unwrap_or_else(|error| ic_cdk::trap(&format!("Cannot restore data aggregator state: {}", error)));
+    let stored_batches = stored_batches.unwrap_or_default();
+
+    DEPENDENCIES.with(|deps| *deps.borrow_mut() = dependencies);
+    VALIDATION_RULES.with(|stored| *stored.borrow_mut() = rules.unwrap_or_default());
+    BATCH_COUNTER.with(|stored| stored.set(counter.unwrap_or_default()));
+    MAPPING_PROFILES.with(|stored| *stored.borrow_mut() = profiles.unwrap_or_default());
+    SYNC_STATUS.with(|stored| *stored.borrow_mut() = sync_status.unwrap_or_default());
+    INTEGRATIONS.with(|stored| *stored.borrow_mut() = integrations.unwrap_or_default());
+    REVIEWERS.with(|stored| *stored.borrow_mut() = reviewers.unwrap_or_default());
+    STATS_RESET_AT.with(|stored| stored.set(stats_reset_at.unwrap_or_default()));
+    MOVEMENT_REPORTS.with(|stored| *stored.borrow_mut() = movement_reports.unwrap_or_default());
+    UPLOAD_HASHES.with(|hashes| {
+        *hashes.borrow_mut() = stored_batches
+            .values()
+            .filter_map(|batch| {
+                let key = upload_key(batch.content_hash.as_deref()?, batch.profile_id.as_deref());
+                Some((key, batch.batch_id.clone()))
+            })
+            .collect();
+    });
+    BATCHES.with(|batches| *batches.borrow_mut() = stored_batches);
 }
 
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use candid::utils::{service_equal, CandidSource};
+    use std::path::Path;
 
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let did_file = Path::new(env!("CARGO_MANIFEST_DIR")).join("data_aggregator.did");
+        service_equal(CandidSource::Text(&super::__export_service()), CandidSource::File(&did_file))
+            .expect("data_aggregator.did is out of date with the Rust interface; run ./generate_did.sh");
+    }
+}
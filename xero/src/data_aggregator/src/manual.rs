@@ -0,0 +1,34 @@
+use candid::{CandidType, Deserialize, Principal};
+use xero_types::{InventoryItemInput, TransactionKind};
+
+use crate::AggregatorError;
+
+/// Most records in one manual entry batch.
+pub const MAX_MANUAL_RECORDS: usize = 1_000;
+
+/// Who entered a manual batch and who reviewed it. A batch is applied only once someone other than
+/// its author and its last editor approves it, so every value is seen by two people.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ManualEntry {
+    pub submitted_by: Principal,
+    pub note: String,
+    pub last_edited_by: Principal,
+    pub reviewed_by: Option<Principal>,
+    pub review_comment: Option<String>,
+    pub ledger_transaction_id: Option<u64>, // the recorded approval or rejection
+}
+
+/// Records a review decision on `records` in the ledger and returns its transaction ID.
+/// Keyed by the batch and a hash of the reviewer, records and decision, so a retried call can't record the
+/// same review twice, while a draft sent back after a failed call can still be reviewed again once edited.
+pub async fn record_review(
+    kind: TransactionKind,
+    reviewer: Principal,
+    batch_id: &str,
+    records: &[InventoryItemInput],
+) -> Result<u64, AggregatorError> {
+    // Encoding plain data can't fail
+    let reviewed = candid::encode_args((reviewer, records, &kind)).unwrap_or_default();
+    let idempotency_key = format!("manual-review:{}:{}", batch_id, crate::sha256_hex(&reviewed));
+    crate::record_in_ledger(kind, reviewer.to_text(), idempotency_key).await
+}
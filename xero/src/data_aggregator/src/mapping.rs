@@ -0,0 +1,388 @@
+use candid::{CandidType, Deserialize};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::{HashMap, HashSet};
+use xero_types::money::{is_valid_currency, parse_decimal, DEFAULT_CURRENCY};
+use xero_types::units::MILLI_PER_UNIT;
+use xero_types::{InventoryItemInput, Money, Quantity, RoundingMode, UnitOfMeasure};
+
+use crate::validation::ItemField;
+use crate::RecordError;
+
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// How a supplier's or POS system's export maps onto inventory items.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MappingProfile {
+    pub profile_id: String,
+    pub name: String,
+    pub delimiter: Option<String>, // a single ASCII character; defaults to ","
+    pub currency: Option<String>, // currency of the price column; defaults to USD
+    pub columns: Vec<ColumnMapping>,
+    pub decimal_separator: Option<String>, // a single character; defaults to "."
+    // A single character, or "" for none; defaults to "," or, when the decimal separator is ",", to "."
+    pub thousands_separator: Option<String>,
+}
+
+/// How numbers are written in a file, e.g. "1.234,5" with `decimal: ','` and `thousands: Some('.')`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    pub thousands: Option<char>,
+}
+
+impl NumberFormat {
+    /// Rewrites `value` as a plain decimal with '.' and no grouping, as `parse_decimal` expects.
+    /// Groups after the first must have three digits, so a misconfigured separator fails instead of
+    /// shifting the value, e.g. "1,5" isn't read as 15 when "," separates thousands.
+    pub fn normalize(&self, value: &str) -> Option<String> {
+        let (whole, fraction) = match value.split_once(self.decimal) {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (value, None),
+        };
+        let whole = match self.thousands {
+            Some(separator) if whole.contains(separator) => {
+                let mut groups = whole.split(separator);
+                let first = groups.next().unwrap_or_default();
+                if first.is_empty() || first.len() > 3 || !groups.all(|group| group.len() == 3) {
+                    return None;
+                }
+                whole.replace(separator, "")
+            }
+            _ => whole.to_string(),
+        };
+        let fraction = fraction.unwrap_or_default();
+        if !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(if fraction.is_empty() { whole } else { format!("{}.{}", whole, fraction) })
+    }
+}
+
+/// Source column (matched case-insensitively against the header row) feeding one item field.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ColumnMapping {
+    pub column: String,
+    pub field: ItemField,
+    pub transform: Option<Transform>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Transform {
+    /// chrono format of the date column, e.g. "%d/%m/%Y"; dates without a time mean midnight UTC.
+    DateFormat { format: String },
+    /// The quantity column counts `from`; stored as `to`, e.g. grams stored as kilograms.
+    ConvertUnit { from: UnitOfMeasure, to: UnitOfMeasure },
+    /// Multiplies the price by `numerator / denominator`, e.g. 1/100 for columns holding cents.
+    ScalePrice { numerator: u64, denominator: u64 },
+    /// Replaces source category labels; labels not listed pass through to category resolution.
+    CategoryLookup { labels: Vec<(String, String)> },
+}
+
+/// The first rows of a file as a profile would import them.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MappingPreview {
+    pub columns: Vec<String>, // header row of the file
+    pub unmapped_columns: Vec<String>, // profile columns missing from the header
+    pub rows: Vec<InventoryItemInput>,
+    pub errors: Vec<RecordError>,
+}
+
+/// Rows parsed from a file; rows in `errors` couldn't be read and hold defaults where a value failed.
+pub struct ParsedRows {
+    pub columns: Vec<String>,
+    pub items: Vec<InventoryItemInput>,
+    pub errors: Vec<RecordError>,
+}
+
+/// Checks that the profile can be applied, returning the reason if not.
+pub fn check_profile(profile: &MappingProfile) -> Result<(), String> {
+    if profile.profile_id.trim().is_empty() {
+        return Err("Profile ID cannot be empty".to_string());
+    }
+    delimiter(profile)?;
+    number_format(profile)?;
+    if let Some(currency) = &profile.currency {
+        if !is_valid_currency(currency) {
+            return Err(format!("Unknown currency '{}'", currency));
+        }
+    }
+    if !profile.columns.iter().any(|c| c.field == ItemField::ItemId) {
+        return Err("A column must map to ItemId".to_string());
+    }
+
+    let mut fields = HashSet::new();
+    for mapping in &profile.columns {
+        if !fields.insert(mapping.field) {
+            return Err(format!("{:?} is mapped more than once", mapping.field));
+        }
+        match (&mapping.transform, mapping.field) {
+            (None, _) => {}
+            (Some(Transform::DateFormat { .. }), ItemField::ExpirationDate) => {}
+            (Some(Transform::ConvertUnit { from, to }), ItemField::Quantity) => {
+                Quantity::whole(1, *from)
+                    .convert_to(*to)
+                    .ok_or_else(|| format!("Cannot convert {:?} to {:?}", from, to))?;
+            }
+            (Some(Transform::ScalePrice { denominator, .. }), ItemField::Price) => {
+                if *denominator == 0 {
+                    return Err("Price scale denominator cannot be zero".to_string());
+                }
+            }
+            (Some(Transform::CategoryLookup { .. }), ItemField::Category) => {}
+            (Some(transform), field) => return Err(format!("{:?} does not apply to {:?}", transform, field)),
+        }
+    }
+    Ok(())
+}
+
+fn delimiter(profile: &MappingProfile) -> Result<u8, String> {
+    match profile.delimiter.as_deref() {
+        None => Ok(b','),
+        Some(d) if d.len() == 1 && d.is_ascii() => Ok(d.as_bytes()[0]),
+        Some(d) => Err(format!("Delimiter '{}' must be a single ASCII character", d)),
+    }
+}
+
+/// The profile's separators, falling back to "." for decimals and "," for thousands.
+pub fn number_format(profile: &MappingProfile) -> Result<NumberFormat, String> {
+    let single = |separator: &str| {
+        let mut chars = separator.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_ascii_digit() && c != '-' => Ok(c),
+            _ => Err(format!("Separator '{}' must be a single character other than a digit or '-'", separator)),
+        }
+    };
+    let decimal = profile.decimal_separator.as_deref().map_or(Ok('.'), single)?;
+    let thousands = match profile.thousands_separator.as_deref() {
+        Some("") => None,
+        Some(separator) => Some(single(separator)?),
+        None if decimal == ',' => Some('.'),
+        None => Some(','),
+    };
+    if thousands == Some(decimal) {
+        return Err("Decimal and thousands separators must differ".to_string());
+    }
+    Ok(NumberFormat { decimal, thousands })
+}
+
+/// Parses delimited text with a header row through the profile, stopping after `limit` rows if given.
+pub fn parse(data: &[u8], profile: &MappingProfile, limit: Option<usize>) -> Result<ParsedRows, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter(profile)?)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    let columns: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("Cannot read the header row: {}", e))?
+        .iter()
+        .map(str::to_string)
+        .collect();
+    let positions: HashMap<String, usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| (column.to_lowercase(), i))
+        .collect();
+
+    let currency = profile.currency.as_deref().unwrap_or(DEFAULT_CURRENCY);
+    let numbers = number_format(profile)?;
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    for (row, record) in reader.records().take(limit.unwrap_or(usize::MAX)).enumerate() {
+        let row_index = row as u32;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                // Keep the row so indexes line up with the file; it is quarantined with the parse error
+                items.push(empty_item(currency));
+                errors.push(parse_error(row_index, "", format!("Unreadable row: {}", e)));
+                continue;
+            }
+        };
+
+        let mut item = empty_item(currency);
+        let mut row_errors = Vec::new();
+        for mapping in &profile.columns {
+            let value = positions
+                .get(&mapping.column.to_lowercase())
+                .and_then(|i| record.get(*i))
+                .unwrap_or_default();
+            if let Err(reason) = apply(&mut item, mapping, value, currency, numbers) {
+                row_errors.push(format!("{}: {}", mapping.column, reason));
+            }
+        }
+        for reason in row_errors {
+            errors.push(parse_error(row_index, &item.item_id, reason));
+        }
+        items.push(item);
+    }
+
+    Ok(ParsedRows { columns, items, errors })
+}
+
+/// Parses the first `rows` rows and reports which profile columns the file lacks.
+pub fn preview(data: &[u8], profile: &MappingProfile, rows: usize) -> Result<MappingPreview, String> {
+    let parsed = parse(data, profile, Some(rows))?;
+    let present: HashSet<String> = parsed.columns.iter().map(|c| c.to_lowercase()).collect();
+    let unmapped_columns = profile
+        .columns
+        .iter()
+        .filter(|m| !present.contains(&m.column.to_lowercase()))
+        .map(|m| m.column.clone())
+        .collect();
+    Ok(MappingPreview {
+        columns: parsed.columns,
+        unmapped_columns,
+        rows: parsed.items,
+        errors: parsed.errors,
+    })
+}
+
+fn empty_item(currency: &str) -> InventoryItemInput {
+    InventoryItemInput {
+        item_id: String::new(),
+        barcode: String::new(),
+        name: String::new(),
+        category: None,
+        quantity: Quantity::whole(0, UnitOfMeasure::Each),
+        expiration_date: 0,
+        price: Money::new(0, currency),
+    }
+}
+
+fn parse_error(row_index: u32, item_id: &str, message: String) -> RecordError {
+    RecordError {
+        row_index,
+        item_id: item_id.to_string(),
+        rule_id: None,
+        message,
+    }
+}
+
+// Blank cells leave the field at its default, for the validation rules to judge
+fn apply(
+    item: &mut InventoryItemInput,
+    mapping: &ColumnMapping,
+    value: &str,
+    currency: &str,
+    numbers: NumberFormat,
+) -> Result<(), String> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    match mapping.field {
+        ItemField::ItemId => item.item_id = value.to_string(),
+        ItemField::Barcode => item.barcode = value.to_string(),
+        ItemField::Name => item.name = value.to_string(),
+        ItemField::Category => {
+            let label = match &mapping.transform {
+                Some(Transform::CategoryLookup { labels }) => labels
+                    .iter()
+                    .find(|(from, _)| from.eq_ignore_ascii_case(value))
+                    .map_or(value, |(_, to)| to.as_str()),
+                _ => value,
+            };
+            item.category = Some(label.to_string());
+        }
+        ItemField::Quantity => {
+            let milli_units = numbers
+                .normalize(value)
+                .and_then(|decimal| parse_decimal(&decimal, MILLI_PER_UNIT.ilog10()))
+                .ok_or_else(|| format!("'{}' is not a non-negative number", value))?;
+            let quantity = match mapping.transform {
+                Some(Transform::ConvertUnit { from, to }) => Quantity { milli_units, unit: from }
+                    .convert_to(to)
+                    .ok_or_else(|| format!("Cannot convert {:?} to {:?}", from, to))?,
+                _ => Quantity { milli_units, unit: UnitOfMeasure::Each },
+            };
+            item.quantity = quantity;
+        }
+        ItemField::ExpirationDate => {
+            let format = match &mapping.transform {
+                Some(Transform::DateFormat { format }) => format.as_str(),
+                _ => DEFAULT_DATE_FORMAT,
+            };
+            item.expiration_date = parse_date(value, format)?;
+        }
+        ItemField::Price => {
+            // Skips a leading currency symbol such as "$" or "EUR "
+            let amount =
+                value.trim_start_matches(|c: char| !c.is_ascii_digit() && c != numbers.decimal && c != '-');
+            let price = numbers
+                .normalize(amount)
+                .and_then(|amount| Money::from_major_str(&amount, currency))
+                .ok_or_else(|| format!("'{}' is not a price", value))?;
+            item.price = match mapping.transform {
+                Some(Transform::ScalePrice { numerator, denominator }) => price
+                    .scale(numerator, denominator, RoundingMode::HalfUp)
+                    .ok_or_else(|| format!("'{}' is out of range once scaled", value))?,
+                _ => price,
+            };
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_date(value: &str, format: &str) -> Result<u64, String> {
+    let datetime = NaiveDateTime::parse_from_str(value, format)
+        .or_else(|_| NaiveDate::parse_from_str(value, format).map(|date| date.and_time(Default::default())))
+        .map_err(|_| format!("'{}' does not match the date format '{}'", value, format))?;
+    datetime
+        .and_utc()
+        .timestamp_nanos_opt()
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .ok_or_else(|| format!("'{}' is out of range", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(decimal: Option<&str>, thousands: Option<&str>) -> MappingProfile {
+        MappingProfile {
+            profile_id: "supplier".to_string(),
+            name: "Supplier".to_string(),
+            delimiter: Some(";".to_string()),
+            currency: Some("EUR".to_string()),
+            columns: vec![
+                ColumnMapping { column: "SKU".to_string(), field: ItemField::ItemId, transform: None },
+                ColumnMapping { column: "Qty".to_string(), field: ItemField::Quantity, transform: None },
+                ColumnMapping { column: "Price".to_string(), field: ItemField::Price, transform: None },
+            ],
+            decimal_separator: decimal.map(str::to_string),
+            thousands_separator: thousands.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn european_numbers_use_the_profile_separators() {
+        let parsed = parse(b"SKU;Qty;Price\nA;1,5;EUR 1.234,56\n", &profile(Some(","), None), None).unwrap();
+        assert!(parsed.errors.is_empty(), "{:?}", parsed.errors);
+        assert_eq!(parsed.items[0].quantity.milli_units, 1_500);
+        assert_eq!(parsed.items[0].price, Money::new(123_456, "EUR"));
+    }
+
+    #[test]
+    fn misplaced_thousands_separators_are_rejected() {
+        let parsed = parse(b"SKU;Qty;Price\nA;1,5;1,000.5\n", &profile(None, None), None).unwrap();
+        assert_eq!(parsed.errors.len(), 1);
+        assert!(parsed.errors[0].message.starts_with("Qty"));
+        assert_eq!(parsed.items[0].price, Money::new(100_050, "EUR"));
+    }
+
+    #[test]
+    fn prices_round_half_up_without_floats() {
+        let numbers = number_format(&profile(None, Some(""))).unwrap();
+        assert_eq!(numbers.normalize("6.993").as_deref(), Some("6.993"));
+        assert_eq!(Money::from_major_str("6.993", "USD"), Some(Money::new(699, "USD")));
+        assert_eq!(Money::from_major_str("0.285", "USD"), Some(Money::new(29, "USD")));
+        assert_eq!(numbers.normalize("1,000"), None);
+    }
+
+    #[test]
+    fn separators_must_differ() {
+        assert!(check_profile(&profile(Some(","), Some(","))).is_err());
+        assert!(check_profile(&profile(Some("12"), None)).is_err());
+        assert!(check_profile(&profile(Some(","), Some(" "))).is_ok());
+    }
+}
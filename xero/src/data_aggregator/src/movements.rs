@@ -0,0 +1,181 @@
+use candid::{CandidType, Deserialize};
+use xero_types::inventory::{InventoryError, StockLevelChange, StockMovement};
+use xero_types::{CallFailure, Quantity, TransactionKind};
+
+use crate::{AggregatorError, RecordError, MOVEMENT_REPORTS};
+
+/// Reports are dropped this long after their message arrived; resending it later applies it again.
+const REPORT_RETENTION_NANOS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+/// Most reports kept; the oldest are dropped first.
+const MAX_REPORTS: usize = 10_000;
+
+/// Inventory's answer to `apply_stock_movements`, one result per movement.
+type MovementResults = Vec<Result<StockLevelChange, InventoryError>>;
+
+/// What became of one line of an `ingest_movements` message.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MovementLine {
+    pub row_index: u32,
+    pub item_id: String,
+    pub movement: Option<StockMovement>, // None when the line couldn't be parsed
+    pub change: Option<StockLevelChange>, // set once inventory applied the movement
+    pub ledger_transaction_id: Option<u64>, // the recorded Sale or StockAdjustment
+    pub error: Option<String>,
+}
+
+/// The outcome of one message, kept so that resending it doesn't change the stock twice.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MovementReport {
+    pub message_key: String, // integration ID and the hex SHA-256 of the payload
+    pub received_at: u64,
+    pub lines: Vec<MovementLine>,
+}
+
+impl MovementReport {
+    pub fn new(message_key: String, movements: Vec<(u32, StockMovement)>, errors: Vec<RecordError>) -> Self {
+        let mut lines: Vec<MovementLine> = movements
+            .into_iter()
+            .map(|(row_index, movement)| MovementLine {
+                row_index,
+                item_id: movement.item_id().to_string(),
+                movement: Some(movement),
+                change: None,
+                ledger_transaction_id: None,
+                error: None,
+            })
+            .chain(errors.into_iter().map(|error| MovementLine {
+                row_index: error.row_index,
+                item_id: error.item_id,
+                movement: None,
+                change: None,
+                ledger_transaction_id: None,
+                error: Some(error.message),
+            }))
+            .collect();
+        lines.sort_by_key(|line| line.row_index);
+        MovementReport {
+            message_key,
+            received_at: ic_cdk::api::time(),
+            lines,
+        }
+    }
+}
+
+/// Stores a report for a new message, pruning expired and surplus ones.
+/// Returns false if the message is already known, e.g. a resend racing the original.
+pub fn insert_report(report: MovementReport) -> bool {
+    let now = ic_cdk::api::time();
+    MOVEMENT_REPORTS.with(|reports| {
+        let mut reports = reports.borrow_mut();
+        if reports.contains_key(&report.message_key) {
+            return false;
+        }
+        reports.retain(|_, stored| now.saturating_sub(stored.received_at) <= REPORT_RETENTION_NANOS);
+        if reports.len() >= MAX_REPORTS {
+            let mut by_age: Vec<(u64, String)> =
+                reports.values().map(|stored| (stored.received_at, stored.message_key.clone())).collect();
+            by_age.sort_unstable();
+            for (_, key) in by_age.iter().take(reports.len() + 1 - MAX_REPORTS) {
+                reports.remove(key);
+            }
+        }
+        reports.insert(report.message_key.clone(), report);
+        true
+    })
+}
+
+pub fn report(message_key: &str) -> Option<MovementReport> {
+    MOVEMENT_REPORTS.with(|reports| reports.borrow().get(message_key).cloned())
+}
+
+fn update_report(message_key: &str, f: impl FnOnce(&mut MovementReport)) {
+    MOVEMENT_REPORTS.with(|reports| {
+        if let Some(report) = reports.borrow_mut().get_mut(message_key) {
+            f(report);
+        }
+    });
+}
+
+/// Sends the parsed movements of a new report to inventory in one call and stores the outcome per line.
+/// If the call fails nothing was applied, so the report is dropped and the message can be sent again.
+pub async fn apply(message_key: &str) -> Result<(), AggregatorError> {
+    let inventory = crate::inventory_canister_id()?;
+    let movements: Vec<StockMovement> = report(message_key)
+        .map(|report| report.lines.into_iter().filter_map(|line| line.movement).collect())
+        .unwrap_or_default();
+
+    let call: Result<(Result<MovementResults, InventoryError>,), _> =
+        ic_cdk::api::call::call(inventory, "apply_stock_movements", (movements,)).await;
+    let results = match call {
+        Ok((Ok(results),)) => Ok(results),
+        Ok((Err(error),)) => Err(AggregatorError::InventoryUpdateFailed { reason: format!("{:?}", error) }),
+        Err(e) => Err(CallFailure::new(inventory, "apply_stock_movements", e).into()),
+    };
+    let results = results.inspect_err(|_| {
+        MOVEMENT_REPORTS.with(|reports| reports.borrow_mut().remove(message_key));
+    })?;
+
+    update_report(message_key, |report| {
+        let parsed = report.lines.iter_mut().filter(|line| line.movement.is_some());
+        for (line, result) in parsed.zip(results) {
+            match result {
+                Ok(change) => line.change = Some(change),
+                Err(error) => line.error = Some(format!("{:?}", error)),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Records every applied line the ledger doesn't have yet. Keyed by message and line, so lines recorded
+/// by an earlier attempt aren't recorded twice; lines the ledger refuses keep the reason for the next resend.
+pub async fn record(message_key: &str, actor_id: &str) {
+    let pending: Vec<(u32, TransactionKind)> = report(message_key)
+        .map(|report| report.lines.iter().filter_map(pending_transaction).collect())
+        .unwrap_or_default();
+
+    for (row_index, kind) in pending {
+        let idempotency_key = format!("stock-movement:{}:{}", message_key, row_index);
+        let recorded = crate::record_in_ledger(kind, actor_id.to_string(), idempotency_key).await;
+        update_report(message_key, |report| {
+            if let Some(line) = report.lines.iter_mut().find(|line| line.row_index == row_index) {
+                match recorded {
+                    Ok(transaction_id) => {
+                        line.ledger_transaction_id = Some(transaction_id);
+                        line.error = None;
+                    }
+                    Err(error) => line.error = Some(format!("Not recorded in the ledger: {:?}", error)),
+                }
+            }
+        });
+    }
+}
+
+fn pending_transaction(line: &MovementLine) -> Option<(u32, TransactionKind)> {
+    let (Some(movement), Some(change), None) = (&line.movement, &line.change, line.ledger_transaction_id) else {
+        return None;
+    };
+    let kind = match movement {
+        StockMovement::Sale { item_id, quantity, unit_price } => TransactionKind::Sale {
+            item_id: item_id.clone(),
+            quantity: *quantity,
+            unit_price: unit_price.clone(),
+        },
+        StockMovement::Adjustment { item_id, reason, .. } => TransactionKind::StockAdjustment {
+            item_id: item_id.clone(),
+            old_quantity: change.old_quantity,
+            new_quantity: change.new_quantity,
+            reason: reason.clone(),
+        },
+        // What actually left the shelf, in the item's unit; stock stops at zero
+        StockMovement::Disposal { item_id, reason, .. } => TransactionKind::WasteDisposal {
+            item_id: item_id.clone(),
+            quantity: Quantity {
+                milli_units: change.old_quantity.milli_units.saturating_sub(change.new_quantity.milli_units),
+                unit: change.old_quantity.unit,
+            },
+            reason: reason.clone(),
+        },
+    };
+    Some((line.row_index, kind))
+}
@@ -0,0 +1,156 @@
+use candid::{CandidType, Deserialize};
+use std::collections::HashMap;
+
+use crate::{DataBatch, DataSource, ProcessingStatus};
+
+pub const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Days in the time series when no window is given.
+pub const DEFAULT_SERIES_DAYS: u32 = 30;
+pub const MAX_SERIES_DAYS: u32 = 365;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SourceStatistics {
+    pub source: DataSource,
+    pub batches: u64,
+    pub records: u64,
+    pub failed_records: u64,
+}
+
+/// Batches settled on one UTC day.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DailyStatistics {
+    pub day_start: u64,
+    pub batches: u64,
+    pub records: u64,
+    pub failed_records: u64,
+}
+
+/// Ingestion health over the retained batches settled since `since`. Each batch counts once, with the
+/// outcome and settlement time of its latest run, so retries and resubmissions aren't counted twice.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProcessingStatistics {
+    pub since: u64,
+    pub total_batches: u64,
+    pub successful_batches: u64,
+    pub partially_completed_batches: u64,
+    pub failed_batches: u64,
+    pub total_records: u64,
+    pub failed_records: u64, // records still quarantined
+    pub success_rate: f64, // percentage of batches imported without errors
+    pub by_source: Vec<SourceStatistics>,
+    pub rule_failures: Vec<(String, u64)>, // quarantined records per validation rule, most frequent first
+    pub average_processing_ms: f64, // summed over each batch's runs
+    pub records_per_second: f64,
+    pub daily: Vec<DailyStatistics>, // oldest first, one per day including days without imports
+}
+
+/// Statistics over `batches` settled since `since`, with a time series of the last `days` days up to `now`.
+pub fn compute<'a>(
+    batches: impl Iterator<Item = &'a DataBatch>,
+    since: u64,
+    now: u64,
+    days: u32,
+) -> ProcessingStatistics {
+    let first_day = (now / NANOS_PER_DAY).saturating_sub(days.saturating_sub(1) as u64) * NANOS_PER_DAY;
+    let mut daily: Vec<DailyStatistics> = (0..days as u64)
+        .map(|day| DailyStatistics {
+            day_start: first_day + day * NANOS_PER_DAY,
+            batches: 0,
+            records: 0,
+            failed_records: 0,
+        })
+        .collect();
+
+    let mut stats = ProcessingStatistics {
+        since,
+        total_batches: 0,
+        successful_batches: 0,
+        partially_completed_batches: 0,
+        failed_batches: 0,
+        total_records: 0,
+        failed_records: 0,
+        success_rate: 0.0,
+        by_source: Vec::new(),
+        rule_failures: Vec::new(),
+        average_processing_ms: 0.0,
+        records_per_second: 0.0,
+        daily: Vec::new(),
+    };
+    let mut rule_failures: HashMap<&str, u64> = HashMap::new();
+    let mut timed_batches = 0u64;
+    let mut timed_records = 0u64;
+    let mut processing_nanos = 0u64;
+
+    for batch in batches {
+        let imported = crate::is_settled(batch) && batch.status != ProcessingStatus::Rejected;
+        let Some(settled_at) = batch.processed_at.filter(|at| imported && *at >= since) else {
+            continue;
+        };
+        let records = batch.data.len() as u64;
+        let failed_records = crate::quarantined_count(batch) as u64;
+
+        stats.total_batches += 1;
+        match batch.status {
+            ProcessingStatus::Completed => stats.successful_batches += 1,
+            ProcessingStatus::PartiallyCompleted => stats.partially_completed_batches += 1,
+            _ => stats.failed_batches += 1,
+        }
+        stats.total_records += records;
+        stats.failed_records += failed_records;
+
+        let index = match stats.by_source.iter().position(|s| s.source == batch.source) {
+            Some(index) => index,
+            None => {
+                stats.by_source.push(SourceStatistics {
+                    source: batch.source,
+                    batches: 0,
+                    records: 0,
+                    failed_records: 0,
+                });
+                stats.by_source.len() - 1
+            }
+        };
+        let source = &mut stats.by_source[index];
+        source.batches += 1;
+        source.records += records;
+        source.failed_records += failed_records;
+
+        for rule_id in batch.record_errors.iter().filter_map(|e| e.rule_id.as_deref()) {
+            *rule_failures.entry(rule_id).or_default() += 1;
+        }
+
+        // Batches processed before processing time was recorded don't have it
+        if let Some(nanos) = batch.processing_nanos {
+            timed_batches += 1;
+            timed_records += records;
+            processing_nanos += nanos;
+        }
+
+        if settled_at >= first_day {
+            if let Some(day) = daily.get_mut(((settled_at - first_day) / NANOS_PER_DAY) as usize) {
+                day.batches += 1;
+                day.records += records;
+                day.failed_records += failed_records;
+            }
+        }
+    }
+
+    if stats.total_batches > 0 {
+        stats.success_rate = (stats.successful_batches as f64 / stats.total_batches as f64) * 100.0;
+    }
+    if timed_batches > 0 {
+        stats.average_processing_ms = processing_nanos as f64 / timed_batches as f64 / 1_000_000.0;
+    }
+    if processing_nanos > 0 {
+        stats.records_per_second = timed_records as f64 / (processing_nanos as f64 / 1_000_000_000.0);
+    }
+    stats.rule_failures = rule_failures
+        .into_iter()
+        .map(|(rule_id, count)| (rule_id.to_string(), count))
+        .collect();
+    stats
+        .rule_failures
+        .sort_by(|(a_id, a_count), (b_id, b_count)| b_count.cmp(a_count).then_with(|| a_id.cmp(b_id)));
+    stats.daily = daily;
+    stats
+}
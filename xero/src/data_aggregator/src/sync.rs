@@ -0,0 +1,295 @@
+use candid::{CandidType, Deserialize, Principal};
+use std::collections::HashMap;
+use xero_types::inventory::{InventoryError, ItemDetails, StockLevelChange};
+use xero_types::{CallFailure, InventoryItem, InventoryItemInput, Money, Quantity};
+
+use crate::movements::MovementReport;
+use crate::{AggregatorError, DataBatch, ProcessingStatus, BATCHES, IMPORT_CHUNK_SIZE, MOVEMENT_REPORTS};
+
+/// Items fetched per `list_items_after` page; audit trails make items large.
+const PAGE_SIZE: u32 = 200;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum SyncDrift {
+    /// Imported but no longer in inventory.
+    MissingFromInventory,
+    QuantityDrift { expected: Quantity, actual: Quantity },
+    PriceDrift { expected: Money, actual: Money },
+}
+
+/// An item whose inventory record differs from its latest import and the movements applied since.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SyncMismatch {
+    pub item_id: String,
+    pub batch_id: String, // batch holding the expected values
+    pub drift: SyncDrift,
+    pub fixed: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SyncReport {
+    pub started_at: u64,
+    pub completed_at: u64,
+    pub inventory_items: u64,
+    pub imported_items: u64, // distinct items in the snapshot compared against
+    pub not_imported_items: u64, // in inventory but in no import, e.g. entered by hand
+    pub mismatches: Vec<SyncMismatch>,
+    pub fix_errors: Vec<String>, // items `fix` tried and inventory rejected
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SyncStatus {
+    pub last_sync: Option<u64>, // completion time of the last successful run
+    pub running: bool,
+    pub last_error: Option<String>, // why the latest run failed; cleared by a successful one
+    pub report: Option<SyncReport>,
+}
+
+/// The latest imported record of each item, with the batch it came from, moved on by the sales and stock
+/// corrections applied since. Movements change inventory without a batch, so they aren't drift, and `fix`
+/// must not undo them. A movement counts as later than an import when its message arrived after the batch
+/// finished processing. Reports outlive the batches before them unless `MAX_REPORTS` is reached.
+fn expected_state(
+    batches: &HashMap<String, DataBatch>,
+    reports: &HashMap<String, MovementReport>,
+) -> HashMap<String, (String, InventoryItemInput)> {
+    let mut settled: Vec<&DataBatch> = batches
+        .values()
+        .filter(|b| crate::is_settled(b) && b.status != ProcessingStatus::Rejected)
+        .collect();
+    settled.sort_by_key(|b| (b.processed_at.unwrap_or(b.created_at), b.batch_id.clone()));
+
+    let mut snapshot = HashMap::new();
+    for batch in settled {
+        let quarantined = crate::quarantined_rows(batch);
+        let imported_at = batch.processed_at.unwrap_or(batch.created_at);
+        for (row, item) in batch.data.iter().enumerate() {
+            if quarantined.binary_search(&row).is_err() {
+                snapshot.insert(item.item_id.clone(), (batch.batch_id.clone(), item.clone(), imported_at));
+            }
+        }
+    }
+
+    for report in reports.values() {
+        for change in report.lines.iter().filter_map(|line| line.change.as_ref()) {
+            if let Some((_, item, imported_at)) = snapshot.get_mut(&change.item_id) {
+                if report.received_at > *imported_at {
+                    apply_change(&mut item.quantity, change);
+                }
+            }
+        }
+    }
+    snapshot
+        .into_iter()
+        .map(|(item_id, (batch_id, item, _))| (item_id, (batch_id, item)))
+        .collect()
+}
+
+// Adds the net change of one applied movement, converted to the imported unit; stock stops at zero
+fn apply_change(quantity: &mut Quantity, change: &StockLevelChange) {
+    let (Some(old), Some(new)) = (
+        change.old_quantity.convert_to(quantity.unit),
+        change.new_quantity.convert_to(quantity.unit),
+    ) else {
+        return;
+    };
+    quantity.milli_units = if new.milli_units >= old.milli_units {
+        quantity.milli_units.saturating_add(new.milli_units - old.milli_units)
+    } else {
+        quantity.milli_units.saturating_sub(old.milli_units - new.milli_units)
+    };
+}
+
+// Paged by item_id, so items added or removed mid-sync don't shift the others out of view
+async fn fetch_inventory(inventory: Principal) -> Result<HashMap<String, ItemDetails>, AggregatorError> {
+    let mut items = HashMap::new();
+    let mut after: Option<String> = None;
+    loop {
+        let (page,): (Vec<InventoryItem>,) =
+            ic_cdk::api::call::call(inventory, "list_items_after", (after, Some(PAGE_SIZE)))
+                .await
+                .map_err(|e| CallFailure::new(inventory, "list_items_after", e))?;
+        let complete = page.len() < PAGE_SIZE as usize;
+        after = page.last().map(|item| item.item_id.clone());
+        items.extend(page.iter().map(|item| (item.item_id.clone(), ItemDetails::from(item))));
+        if complete {
+            return Ok(items);
+        }
+    }
+}
+
+// An item added after the listing passed its place looks missing; asking for it directly settles that
+async fn confirm_missing(inventory: Principal, item_id: &str) -> Result<Option<ItemDetails>, AggregatorError> {
+    let (result,): (Result<ItemDetails, InventoryError>,) =
+        ic_cdk::api::call::call(inventory, "get_item_details", (item_id,))
+            .await
+            .map_err(|e| CallFailure::new(inventory, "get_item_details", e))?;
+    match result {
+        Ok(details) => Ok(Some(details)),
+        Err(InventoryError::ItemNotFound { .. }) => Ok(None),
+        Err(error) => Err(AggregatorError::InventoryUpdateFailed { reason: format!("{:?}", error) }),
+    }
+}
+
+fn compare(expected: &InventoryItemInput, actual: Option<&ItemDetails>) -> Vec<SyncDrift> {
+    let Some(actual) = actual else {
+        return vec![SyncDrift::MissingFromInventory];
+    };
+    let mut drifts = Vec::new();
+    // Compared in the imported unit, so 1 kg and 1000 g match
+    if actual.quantity.convert_to(expected.quantity.unit) != Some(expected.quantity) {
+        drifts.push(SyncDrift::QuantityDrift {
+            expected: expected.quantity,
+            actual: actual.quantity,
+        });
+    }
+    if actual.price != expected.price {
+        drifts.push(SyncDrift::PriceDrift {
+            expected: expected.price.clone(),
+            actual: actual.price.clone(),
+        });
+    }
+    drifts
+}
+
+/// Compares inventory against the latest import of each item, moved on by later stock movements, and with
+/// `fix` writes those expected records back.
+pub async fn reconcile(inventory: Principal, fix: bool) -> Result<SyncReport, AggregatorError> {
+    let started_at = ic_cdk::api::time();
+    let snapshot = BATCHES.with(|batches| {
+        MOVEMENT_REPORTS.with(|reports| expected_state(&batches.borrow(), &reports.borrow()))
+    });
+    let mut current = fetch_inventory(inventory).await?;
+
+    let mut item_ids: Vec<&String> = snapshot.keys().collect();
+    item_ids.sort();
+    // Only items confirmed missing are reported, and so imported again by `fix`
+    let unlisted: Vec<&String> =
+        item_ids.iter().copied().filter(|item_id| !current.contains_key(*item_id)).collect();
+    for item_id in unlisted {
+        if let Some(details) = confirm_missing(inventory, item_id).await? {
+            current.insert(item_id.clone(), details);
+        }
+    }
+    let mut mismatches = Vec::new();
+    for item_id in item_ids {
+        let (batch_id, expected) = &snapshot[item_id];
+        for drift in compare(expected, current.get(item_id)) {
+            mismatches.push(SyncMismatch {
+                item_id: item_id.clone(),
+                batch_id: batch_id.clone(),
+                drift,
+                fixed: false,
+            });
+        }
+    }
+
+    let mut fix_errors = Vec::new();
+    if fix {
+        let mut to_fix: Vec<&String> = mismatches.iter().map(|m| &m.item_id).collect();
+        to_fix.dedup(); // mismatches are grouped by item
+        let mut fixed = Vec::new();
+        for chunk in to_fix.chunks(IMPORT_CHUNK_SIZE) {
+            let items = chunk.iter().map(|item_id| &snapshot[*item_id].1).collect();
+            for (item_id, result) in chunk.iter().zip(crate::import_items(inventory, items).await?) {
+                match result {
+                    Ok(_) => fixed.push((*item_id).clone()),
+                    Err(error) => fix_errors.push(format!("{}: {:?}", item_id, error)),
+                }
+            }
+        }
+        for mismatch in mismatches.iter_mut() {
+            mismatch.fixed = fixed.binary_search(&mismatch.item_id).is_ok();
+        }
+    }
+
+    Ok(SyncReport {
+        started_at,
+        completed_at: ic_cdk::api::time(),
+        inventory_items: current.len() as u64,
+        imported_items: snapshot.len() as u64,
+        not_imported_items: current.keys().filter(|id| !snapshot.contains_key(*id)).count() as u64,
+        mismatches,
+        fix_errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movements::MovementLine;
+    use xero_types::UnitOfMeasure;
+
+    fn batch(batch_id: &str, processed_at: u64, quantity: Quantity) -> DataBatch {
+        DataBatch {
+            batch_id: batch_id.to_string(),
+            source: crate::DataSource::API,
+            data: vec![InventoryItemInput {
+                item_id: "milk".to_string(),
+                barcode: String::new(),
+                name: "Milk".to_string(),
+                category: None,
+                quantity,
+                expiration_date: 0,
+                price: Money::new(199, "USD"),
+            }],
+            status: ProcessingStatus::Completed,
+            created_at: processed_at,
+            processed_at: Some(processed_at),
+            record_errors: Vec::new(),
+            content_hash: None,
+            profile_id: None,
+            manual: None,
+            processing_nanos: None,
+        }
+    }
+
+    fn report(received_at: u64, old_quantity: Quantity, new_quantity: Quantity) -> MovementReport {
+        MovementReport {
+            message_key: format!("pos:{}", received_at),
+            received_at,
+            lines: vec![MovementLine {
+                row_index: 0,
+                item_id: "milk".to_string(),
+                movement: None,
+                change: Some(StockLevelChange {
+                    item_id: "milk".to_string(),
+                    old_quantity,
+                    new_quantity,
+                }),
+                ledger_transaction_id: None,
+                error: None,
+            }],
+        }
+    }
+
+    fn expected_quantity(batches: Vec<DataBatch>, reports: Vec<MovementReport>) -> Quantity {
+        let batches = batches.into_iter().map(|b| (b.batch_id.clone(), b)).collect();
+        let reports = reports.into_iter().map(|r| (r.message_key.clone(), r)).collect();
+        expected_state(&batches, &reports)["milk"].1.quantity
+    }
+
+    #[test]
+    fn movements_after_the_last_import_move_the_expected_stock() {
+        let each = |units| Quantity::whole(units, UnitOfMeasure::Each);
+        let sold_before = report(50, each(12), each(10));
+        let sold_after = report(150, each(10), each(7));
+        let restocked = report(160, each(7), each(9));
+        let reports = vec![sold_before, sold_after, restocked];
+        assert_eq!(expected_quantity(vec![batch("B1", 100, each(10))], reports), each(9));
+
+        // A later import replaces the stock, so earlier movements no longer count
+        let sold_after = report(150, each(10), each(7));
+        let batches = vec![batch("B1", 100, each(10)), batch("B2", 200, each(20))];
+        assert_eq!(expected_quantity(batches, vec![sold_after]), each(20));
+    }
+
+    #[test]
+    fn movements_convert_to_the_imported_unit_and_stop_at_zero() {
+        let kg = |milli_units| Quantity { milli_units, unit: UnitOfMeasure::Kilogram };
+        let grams = |units| Quantity::whole(units, UnitOfMeasure::Gram);
+        let sold = report(150, grams(900), grams(400));
+        assert_eq!(expected_quantity(vec![batch("B1", 100, kg(2_000))], vec![sold]), kg(1_500));
+        assert_eq!(expected_quantity(vec![batch("B1", 100, kg(200))], vec![report(150, kg(900), kg(0))]), kg(0));
+    }
+}
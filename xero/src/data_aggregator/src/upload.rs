@@ -0,0 +1,176 @@
+use candid::{CandidType, Deserialize, Principal};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::{AggregatorError, Registered, UPLOADS};
+
+/// Largest file accepted through an upload session.
+pub const MAX_UPLOAD_SIZE: u64 = 64 * 1024 * 1024;
+/// Largest chunk, leaving room in the 2 MiB ingress message for the other arguments.
+pub const MAX_CHUNK_SIZE: usize = 1_900_000;
+/// Sessions are dropped a day after they were opened, unless their file is still being imported.
+const SESSION_TTL_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Most sessions one principal may have open, i.e. receiving chunks or waiting to be parsed.
+const MAX_OPEN_SESSIONS_PER_CALLER: usize = 4;
+/// Most bytes one principal may have announced across its open sessions.
+const MAX_OPEN_BYTES_PER_CALLER: u64 = 2 * MAX_UPLOAD_SIZE;
+const MAX_OPEN_SESSIONS: usize = 32;
+/// Most bytes announced across all open sessions, bounding what uploads can hold on the heap.
+const MAX_OPEN_BYTES: u64 = 8 * MAX_UPLOAD_SIZE;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum UploadStatus {
+    Receiving,
+    /// Hash checked; the file is parsed and imported in the background.
+    Parsing,
+    Processing { batch_id: String },
+    Completed { batch_id: String },
+    Failed { reason: String },
+}
+
+/// An upload in progress, sent as numbered chunks by its owner.
+pub struct UploadSession {
+    pub owner: Principal,
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String, // lowercase hex, as announced by `begin_upload`
+    pub profile_id: Option<String>,
+    pub chunks: BTreeMap<u32, Vec<u8>>,
+    pub status: UploadStatus,
+    pub created_at: u64,
+}
+
+/// What the frontend polls while a file uploads and imports.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct UploadProgress {
+    pub session_id: String,
+    pub filename: String,
+    pub size: u64,
+    pub received_bytes: u64,
+    pub status: UploadStatus,
+}
+
+impl UploadSession {
+    pub fn progress(&self, session_id: &str) -> UploadProgress {
+        UploadProgress {
+            session_id: session_id.to_string(),
+            filename: self.filename.clone(),
+            size: self.size,
+            // Chunks are dropped once the file is assembled
+            received_bytes: if self.status == UploadStatus::Receiving {
+                self.received_bytes()
+            } else {
+                self.size
+            },
+            status: self.status.clone(),
+        }
+    }
+
+    pub fn received_bytes(&self) -> u64 {
+        self.chunks.values().map(|chunk| chunk.len() as u64).sum()
+    }
+
+    /// Joins the chunks once they are contiguous from 0 and add up to the announced size and hash.
+    pub fn assemble(&self) -> Result<Vec<u8>, AggregatorError> {
+        if let Some((expected, _)) = self.chunks.keys().enumerate().find(|(i, index)| *i as u32 != **index) {
+            return Err(invalid(format!("Chunk {} is missing", expected)));
+        }
+        let received = self.received_bytes();
+        if received != self.size {
+            return Err(invalid(format!("Received {} of {} bytes", received, self.size)));
+        }
+
+        let data: Vec<u8> = self.chunks.values().flatten().copied().collect();
+        let actual = crate::sha256_hex(&data);
+        if actual != self.sha256 {
+            return Err(AggregatorError::HashMismatch {
+                expected: self.sha256.clone(),
+                actual,
+            });
+        }
+        Ok(data)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        let importing = matches!(self.status, UploadStatus::Parsing | UploadStatus::Processing { .. });
+        !importing && now.saturating_sub(self.created_at) > SESSION_TTL_NANOS
+    }
+}
+
+/// Checks that a new session of `size` bytes for `owner` stays within the per-caller and overall limits.
+/// Open sessions count with their announced size, since that is what they may still buffer.
+pub fn check_limits(
+    sessions: &HashMap<String, UploadSession>,
+    owner: Principal,
+    size: u64,
+) -> Result<(), AggregatorError> {
+    let open: Vec<&UploadSession> = sessions
+        .values()
+        .filter(|session| matches!(session.status, UploadStatus::Receiving | UploadStatus::Parsing))
+        .collect();
+    let (owned_sessions, owned_bytes) = open
+        .iter()
+        .filter(|session| session.owner == owner)
+        .fold((0, 0), |(count, bytes), session| (count + 1, bytes + session.size));
+    let open_bytes: u64 = open.iter().map(|session| session.size).sum();
+
+    let reason = if owned_sessions >= MAX_OPEN_SESSIONS_PER_CALLER {
+        format!("At most {} uploads may be open per caller", MAX_OPEN_SESSIONS_PER_CALLER)
+    } else if owned_bytes + size > MAX_OPEN_BYTES_PER_CALLER {
+        format!("Open uploads may total at most {} bytes per caller", MAX_OPEN_BYTES_PER_CALLER)
+    } else if open.len() >= MAX_OPEN_SESSIONS {
+        format!("At most {} uploads may be open at once", MAX_OPEN_SESSIONS)
+    } else if open_bytes + size > MAX_OPEN_BYTES {
+        format!("Open uploads may total at most {} bytes", MAX_OPEN_BYTES)
+    } else {
+        return Ok(());
+    };
+    Err(AggregatorError::UploadLimitReached { reason })
+}
+
+pub fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn invalid(reason: String) -> AggregatorError {
+    AggregatorError::InvalidUpload { reason }
+}
+
+/// Parses and imports a committed file on a fresh message, so `commit_upload` returns right away.
+// Timers don't survive upgrades; a session caught in `Parsing` then has to be uploaded again.
+pub fn schedule_parse(session_id: String, data: Vec<u8>, profile_id: Option<String>) {
+    ic_cdk_timers::set_timer(Duration::ZERO, move || {
+        ic_cdk::spawn(parse_upload(session_id, data, profile_id))
+    });
+}
+
+async fn parse_upload(session_id: String, data: Vec<u8>, profile_id: Option<String>) {
+    let status = match import_file(&session_id, data, profile_id).await {
+        Ok(batch_id) => UploadStatus::Completed { batch_id },
+        Err(error) => UploadStatus::Failed {
+            reason: format!("{:?}", error),
+        },
+    };
+    set_status(&session_id, status);
+}
+
+async fn import_file(session_id: &str, data: Vec<u8>, profile_id: Option<String>) -> Result<String, AggregatorError> {
+    let batch = crate::parse_file(&data, profile_id).await?;
+    let batch_id = match crate::register_batch(batch)? {
+        Registered::New(batch_id) => batch_id,
+        // The same file was imported before; point the session at that batch
+        Registered::Existing(existing) => return Ok(existing.batch_id),
+    };
+
+    set_status(session_id, UploadStatus::Processing { batch_id: batch_id.clone() });
+    crate::process_pending(batch_id.clone()).await?;
+    Ok(batch_id)
+}
+
+fn set_status(session_id: &str, status: UploadStatus) {
+    UPLOADS.with(|uploads| {
+        if let Some(session) = uploads.borrow_mut().get_mut(session_id) {
+            session.status = status;
+        }
+    });
+}
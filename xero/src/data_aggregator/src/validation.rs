@@ -0,0 +1,390 @@
+use candid::{CandidType, Deserialize};
+use regex::Regex;
+use std::cmp::Ordering;
+use xero_types::inventory::StockMovement;
+use xero_types::money::DEFAULT_CURRENCY;
+use xero_types::{InventoryItemInput, Money, Quantity};
+
+const NANOS_PER_DAY: i128 = 24 * 60 * 60 * 1_000_000_000;
+
+/// A field of `InventoryItemInput` that a rule inspects.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ItemField {
+    ItemId,
+    Barcode,
+    Name,
+    Category,
+    Quantity,
+    ExpirationDate,
+    Price,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ComparisonOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Right-hand side of a comparison; its type must match the field's.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum RuleValue {
+    Text(String),
+    Timestamp(u64),
+    Quantity(Quantity), // compared after converting to the same unit
+    Money(Money), // only prices in the same currency compare
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum RuleCondition {
+    /// Text must be non-blank, the category present, and quantities, prices and dates non-zero.
+    Required,
+    Compare { op: ComparisonOp, value: RuleValue },
+    /// The whole text field must match the regular expression.
+    Matches { pattern: String },
+    /// The date must fall within this many days from the time of validation; negative values reach into the past.
+    DateWindow { min_days_from_now: Option<i64>, max_days_from_now: Option<i64> },
+    /// GTIN-8/12/13/14 check digit.
+    BarcodeChecksum,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ValidationRule {
+    pub rule_id: String,
+    pub field: ItemField,
+    pub condition: RuleCondition,
+    pub error_message: String,
+}
+
+fn is_text(field: ItemField) -> bool {
+    matches!(field, ItemField::ItemId | ItemField::Barcode | ItemField::Name | ItemField::Category)
+}
+
+/// Checks that the rule's condition makes sense for its field, returning the reason if not.
+pub fn check_rule(rule: &ValidationRule) -> Result<(), String> {
+    if rule.rule_id.trim().is_empty() {
+        return Err("Rule ID cannot be empty".to_string());
+    }
+    match &rule.condition {
+        RuleCondition::Required => Ok(()),
+        RuleCondition::Compare { value, .. } => match (rule.field, value) {
+            (field, RuleValue::Text(_)) if is_text(field) => Ok(()),
+            (ItemField::ExpirationDate, RuleValue::Timestamp(_)) => Ok(()),
+            (ItemField::Quantity, RuleValue::Quantity(_)) => Ok(()),
+            (ItemField::Price, RuleValue::Money(_)) => Ok(()),
+            (field, value) => Err(format!("{:?} cannot be compared with {:?}", field, value)),
+        },
+        RuleCondition::Matches { pattern } => {
+            if !is_text(rule.field) {
+                return Err(format!("{:?} is not a text field", rule.field));
+            }
+            compile(pattern).map(|_| ())
+        }
+        RuleCondition::DateWindow { min_days_from_now, max_days_from_now } => {
+            if rule.field != ItemField::ExpirationDate {
+                return Err("Date windows only apply to ExpirationDate".to_string());
+            }
+            match (min_days_from_now, max_days_from_now) {
+                (None, None) => Err("Date window needs at least one bound".to_string()),
+                (Some(min), Some(max)) if min > max => Err("Date window minimum is after its maximum".to_string()),
+                _ => Ok(()),
+            }
+        }
+        RuleCondition::BarcodeChecksum => {
+            if rule.field == ItemField::Barcode {
+                Ok(())
+            } else {
+                Err("Barcode checksums only apply to Barcode".to_string())
+            }
+        }
+    }
+}
+
+// Anchored so the pattern has to match the whole value
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| format!("Invalid pattern: {}", e))
+}
+
+/// Rules prepared for evaluation, with their patterns compiled once per batch.
+pub struct RuleSet<'a> {
+    rules: Vec<(&'a ValidationRule, Option<Regex>)>,
+    now: u64,
+}
+
+impl<'a> RuleSet<'a> {
+    pub fn new(rules: impl IntoIterator<Item = &'a ValidationRule>, now: u64) -> Self {
+        let mut rules: Vec<(&ValidationRule, Option<Regex>)> = rules
+            .into_iter()
+            .map(|rule| {
+                let regex = match &rule.condition {
+                    RuleCondition::Matches { pattern } => compile(pattern).ok(),
+                    _ => None,
+                };
+                (rule, regex)
+            })
+            .collect();
+        // Rules live in a HashMap; sort so errors come out in a stable order
+        rules.sort_by(|(a, _), (b, _)| a.rule_id.cmp(&b.rule_id));
+        RuleSet { rules, now }
+    }
+
+    /// Rules the item fails.
+    pub fn failures(&self, item: &InventoryItemInput) -> Vec<&'a ValidationRule> {
+        self.rules
+            .iter()
+            .filter(|(rule, regex)| !self.passes(item, rule, regex.as_ref()))
+            .map(|(rule, _)| *rule)
+            .collect()
+    }
+
+    /// Rules a stock movement fails, among those on the fields a movement carries: the item ID, the quantity
+    /// moved and a sale's unit price. Rules on other fields describe whole item records and don't apply.
+    pub fn movement_failures(&self, movement: &StockMovement) -> Vec<&'a ValidationRule> {
+        let (item_id, quantity, unit_price) = match movement {
+            StockMovement::Sale { item_id, quantity, unit_price } => (item_id, quantity, Some(unit_price)),
+            StockMovement::Adjustment { item_id, quantity, .. }
+            | StockMovement::Disposal { item_id, quantity, .. } => (item_id, quantity, None),
+        };
+        let item = InventoryItemInput {
+            item_id: item_id.clone(),
+            barcode: String::new(),
+            name: String::new(),
+            category: None,
+            quantity: *quantity,
+            expiration_date: 0,
+            price: unit_price.cloned().unwrap_or_else(|| Money::new(0, DEFAULT_CURRENCY)),
+        };
+        self.rules
+            .iter()
+            .filter(|(rule, _)| match rule.field {
+                ItemField::ItemId | ItemField::Quantity => true,
+                ItemField::Price => unit_price.is_some(),
+                _ => false,
+            })
+            .filter(|(rule, regex)| !self.passes(&item, rule, regex.as_ref()))
+            .map(|(rule, _)| *rule)
+            .collect()
+    }
+
+    fn passes(&self, item: &InventoryItemInput, rule: &ValidationRule, regex: Option<&Regex>) -> bool {
+        let text = match rule.field {
+            ItemField::ItemId => Some(item.item_id.as_str()),
+            ItemField::Barcode => Some(item.barcode.as_str()),
+            ItemField::Name => Some(item.name.as_str()),
+            ItemField::Category => item.category.as_deref(),
+            _ => None,
+        };
+
+        match &rule.condition {
+            RuleCondition::Required => match rule.field {
+                ItemField::Quantity => !item.quantity.is_zero() && item.quantity.is_valid(),
+                ItemField::Price => !item.price.is_zero() && item.price.is_valid(),
+                ItemField::ExpirationDate => item.expiration_date != 0,
+                _ => text.is_some_and(|text| !text.trim().is_empty()),
+            },
+            RuleCondition::Compare { op, value } => {
+                let ordering = match (value, text) {
+                    (RuleValue::Text(expected), Some(text)) => Some(text.cmp(expected.as_str())),
+                    (RuleValue::Timestamp(expected), _) => Some(item.expiration_date.cmp(expected)),
+                    (RuleValue::Quantity(expected), _) => expected
+                        .convert_to(item.quantity.unit)
+                        .map(|expected| item.quantity.milli_units.cmp(&expected.milli_units)),
+                    (RuleValue::Money(expected), _) => (expected.currency == item.price.currency)
+                        .then(|| item.price.minor_units.cmp(&expected.minor_units)),
+                    _ => None,
+                };
+                // Values that can't be compared (no category, other currency) only satisfy `Ne`
+                ordering.map_or(*op == ComparisonOp::Ne, |ordering| compare(*op, ordering))
+            }
+            RuleCondition::Matches { .. } => match (regex, text) {
+                (Some(regex), Some(text)) => regex.is_match(text),
+                _ => false,
+            },
+            RuleCondition::DateWindow { min_days_from_now, max_days_from_now } => {
+                let away = i128::from(item.expiration_date) - i128::from(self.now);
+                min_days_from_now.is_none_or(|min| away >= i128::from(min) * NANOS_PER_DAY)
+                    && max_days_from_now.is_none_or(|max| away <= i128::from(max) * NANOS_PER_DAY)
+            }
+            RuleCondition::BarcodeChecksum => is_valid_gtin(&item.barcode),
+        }
+    }
+}
+
+fn compare(op: ComparisonOp, ordering: Ordering) -> bool {
+    match op {
+        ComparisonOp::Eq => ordering == Ordering::Equal,
+        ComparisonOp::Ne => ordering != Ordering::Equal,
+        ComparisonOp::Lt => ordering == Ordering::Less,
+        ComparisonOp::Le => ordering != Ordering::Greater,
+        ComparisonOp::Gt => ordering == Ordering::Greater,
+        ComparisonOp::Ge => ordering != Ordering::Less,
+    }
+}
+
+// GTIN mod-10: weights alternate 3, 1 from the digit left of the check digit
+fn is_valid_gtin(barcode: &str) -> bool {
+    if ![8, 12, 13, 14].contains(&barcode.len()) || !barcode.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let digits: Vec<u32> = barcode.bytes().map(|b| u32::from(b - b'0')).collect();
+    let (check, body) = digits.split_last().unwrap_or((&0, &[]));
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { *digit })
+        .sum();
+    (10 - sum % 10) % 10 == *check
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xero_types::UnitOfMeasure;
+
+    const NOW: u64 = 1_700_000_000_000_000_000;
+
+    fn item() -> InventoryItemInput {
+        InventoryItemInput {
+            item_id: "SKU-1".to_string(),
+            barcode: "4006381333931".to_string(),
+            name: "Whole milk".to_string(),
+            category: None,
+            quantity: Quantity::whole(2, UnitOfMeasure::Kilogram),
+            expiration_date: NOW,
+            price: Money::new(199, "USD"),
+        }
+    }
+
+    fn rule(field: ItemField, condition: RuleCondition) -> ValidationRule {
+        ValidationRule {
+            rule_id: "rule".to_string(),
+            field,
+            condition,
+            error_message: String::new(),
+        }
+    }
+
+    fn passes(rule: &ValidationRule, item: &InventoryItemInput) -> bool {
+        RuleSet::new([rule], NOW).failures(item).is_empty()
+    }
+
+    fn window(min: Option<i64>, max: Option<i64>) -> ValidationRule {
+        let condition = RuleCondition::DateWindow { min_days_from_now: min, max_days_from_now: max };
+        rule(ItemField::ExpirationDate, condition)
+    }
+
+    fn expiring_in_nanos(nanos: i128) -> InventoryItemInput {
+        InventoryItemInput {
+            expiration_date: (i128::from(NOW) + nanos) as u64,
+            ..item()
+        }
+    }
+
+    #[test]
+    fn gtin_check_digits() {
+        assert!(is_valid_gtin("4006381333931"));
+        assert!(is_valid_gtin("96385074"));
+        assert!(is_valid_gtin("036000291452"));
+        assert!(is_valid_gtin("00036000291452"));
+        assert!(!is_valid_gtin("4006381333932"));
+        assert!(!is_valid_gtin("400638133393"));
+        assert!(!is_valid_gtin("40063813339A1"));
+        assert!(!is_valid_gtin(""));
+    }
+
+    #[test]
+    fn date_window_bounds_are_inclusive() {
+        let next_week = window(Some(0), Some(7));
+        assert!(passes(&next_week, &expiring_in_nanos(0)));
+        assert!(passes(&next_week, &expiring_in_nanos(7 * NANOS_PER_DAY)));
+        assert!(!passes(&next_week, &expiring_in_nanos(7 * NANOS_PER_DAY + 1)));
+        assert!(!passes(&next_week, &expiring_in_nanos(-1)));
+
+        // Negative bounds reach into the past; a missing bound is open
+        let recently_expired = window(Some(-2), None);
+        assert!(passes(&recently_expired, &expiring_in_nanos(-2 * NANOS_PER_DAY)));
+        assert!(!passes(&recently_expired, &expiring_in_nanos(-2 * NANOS_PER_DAY - 1)));
+        assert!(passes(&recently_expired, &expiring_in_nanos(365 * NANOS_PER_DAY)));
+    }
+
+    #[test]
+    fn compare_converts_quantities_and_skips_other_currencies() {
+        let at_least_1500_g = RuleCondition::Compare {
+            op: ComparisonOp::Ge,
+            value: RuleValue::Quantity(Quantity::whole(1_500, UnitOfMeasure::Gram)),
+        };
+        assert!(passes(&rule(ItemField::Quantity, at_least_1500_g.clone()), &item()));
+        let one_kilo = InventoryItemInput {
+            quantity: Quantity::whole(1, UnitOfMeasure::Kilogram),
+            ..item()
+        };
+        assert!(!passes(&rule(ItemField::Quantity, at_least_1500_g), &one_kilo));
+
+        // A price in another currency can't be compared, so only `Ne` holds
+        let euros = |op| RuleCondition::Compare { op, value: RuleValue::Money(Money::new(100, "EUR")) };
+        assert!(!passes(&rule(ItemField::Price, euros(ComparisonOp::Gt)), &item()));
+        assert!(passes(&rule(ItemField::Price, euros(ComparisonOp::Ne)), &item()));
+    }
+
+    #[test]
+    fn movements_only_meet_rules_on_the_fields_they_carry() {
+        let sku = rule(ItemField::ItemId, RuleCondition::Matches { pattern: "SKU-\\d+".to_string() });
+        let required_name = rule(ItemField::Name, RuleCondition::Required);
+        let price_cap = rule(
+            ItemField::Price,
+            RuleCondition::Compare { op: ComparisonOp::Le, value: RuleValue::Money(Money::new(10_000, "USD")) },
+        );
+        let rules = [sku, required_name, price_cap];
+        let rule_set = RuleSet::new(&rules, NOW);
+        let sale = |item_id: &str, cents| StockMovement::Sale {
+            item_id: item_id.to_string(),
+            quantity: Quantity::whole(1, UnitOfMeasure::Each),
+            unit_price: Money::new(cents, "USD"),
+        };
+
+        assert!(rule_set.movement_failures(&sale("SKU-1", 199)).is_empty());
+        // Both the ID pattern and the price cap fail; the name rule is skipped
+        assert_eq!(rule_set.movement_failures(&sale("milk", 20_000)).len(), 2);
+
+        // Adjustments carry no price, so the price rule doesn't apply
+        let adjustment = StockMovement::Adjustment {
+            item_id: "SKU-1".to_string(),
+            quantity: Quantity::whole(1, UnitOfMeasure::Each),
+            increase: false,
+            reason: "damaged".to_string(),
+        };
+        assert!(rule_set.movement_failures(&adjustment).is_empty());
+    }
+
+    #[test]
+    fn required_matches_and_checksum_conditions() {
+        let required_category = rule(ItemField::Category, RuleCondition::Required);
+        assert!(!passes(&required_category, &item()));
+        let categorised = InventoryItemInput {
+            category: Some("Dairy".to_string()),
+            ..item()
+        };
+        assert!(passes(&required_category, &categorised));
+
+        // Patterns are anchored to the whole value
+        let sku = rule(ItemField::ItemId, RuleCondition::Matches { pattern: "SKU-\\d+".to_string() });
+        assert!(passes(&sku, &item()));
+        let prefixed = InventoryItemInput {
+            item_id: "X-SKU-1".to_string(),
+            ..item()
+        };
+        assert!(!passes(&sku, &prefixed));
+
+        let checksum = rule(ItemField::Barcode, RuleCondition::BarcodeChecksum);
+        assert!(passes(&checksum, &item()));
+        let mistyped = InventoryItemInput {
+            barcode: "4006381333932".to_string(),
+            ..item()
+        };
+        assert!(!passes(&checksum, &mistyped));
+    }
+}
@@ -0,0 +1,165 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::HashMap;
+use xero_types::inventory::{InventoryError, ItemCategory, StorageTemperature};
+
+// Seeded on init so existing clients keep working with the old fixed categories
+const DEFAULT_CATEGORIES: [(&str, &str, Option<StorageTemperature>); 6] = [
+    ("produce", "Produce", Some(StorageTemperature::Chilled)),
+    ("dairy", "Dairy", Some(StorageTemperature::Chilled)),
+    ("meat", "Meat", Some(StorageTemperature::Chilled)),
+    ("bakery", "Bakery", Some(StorageTemperature::Ambient)),
+    ("grocery", "Grocery", Some(StorageTemperature::Ambient)),
+    ("other", "Other", None),
+];
+
+/// Category tree plus the table mapping imported labels onto it.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CategoryRegistry {
+    categories: HashMap<String, ItemCategory>,
+    // Normalized source label -> category_id
+    mappings: HashMap<String, String>,
+}
+
+fn normalize_label(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+impl CategoryRegistry {
+    pub fn with_defaults() -> Self {
+        let mut registry = CategoryRegistry::default();
+        for (category_id, name, storage_temperature) in DEFAULT_CATEGORIES {
+            registry.categories.insert(
+                category_id.to_string(),
+                ItemCategory {
+                    category_id: category_id.to_string(),
+                    name: name.to_string(),
+                    parent_id: None,
+                    default_shelf_life_days: None,
+                    storage_temperature,
+                },
+            );
+        }
+        registry
+    }
+
+    pub fn get(&self, category_id: &str) -> Option<&ItemCategory> {
+        self.categories.get(category_id)
+    }
+
+    pub fn list(&self) -> Vec<ItemCategory> {
+        self.categories.values().cloned().collect()
+    }
+
+    pub fn mappings(&self) -> Vec<(String, String)> {
+        self.mappings
+            .iter()
+            .map(|(label, category_id)| (label.clone(), category_id.clone()))
+            .collect()
+    }
+
+    pub fn upsert(&mut self, category: ItemCategory) -> Result<(), InventoryError> {
+        if category.category_id.trim().is_empty() {
+            return Err(InventoryError::invalid("category_id", "Category ID cannot be empty."));
+        }
+        if category.name.trim().is_empty() {
+            return Err(InventoryError::invalid("name", "Category name cannot be empty."));
+        }
+        if let Some(parent_id) = &category.parent_id {
+            if !self.categories.contains_key(parent_id) {
+                return Err(InventoryError::CategoryNotFound { category_id: parent_id.clone() });
+            }
+            if self.lineage(parent_id).contains(&category.category_id) {
+                return Err(InventoryError::invalid("parent_id", "Category cannot be its own ancestor."));
+            }
+        }
+        self.categories.insert(category.category_id.clone(), category);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, category_id: &str) -> Result<ItemCategory, InventoryError> {
+        if self.categories.values().any(|c| c.parent_id.as_deref() == Some(category_id)) {
+            return Err(InventoryError::invalid("category_id", "Category still has subcategories."));
+        }
+        let removed = self
+            .categories
+            .remove(category_id)
+            .ok_or_else(|| InventoryError::CategoryNotFound { category_id: category_id.to_string() })?;
+        self.mappings.retain(|_, target| target != category_id);
+        Ok(removed)
+    }
+
+    /// Category IDs from the root down to `category_id` itself.
+    pub fn lineage(&self, category_id: &str) -> Vec<String> {
+        let mut lineage = Vec::new();
+        let mut current = self.categories.get(category_id);
+        while let Some(category) = current {
+            // Guards against a cycle sneaking in through stable memory
+            if lineage.contains(&category.category_id) {
+                break;
+            }
+            lineage.push(category.category_id.clone());
+            current = category.parent_id.as_ref().and_then(|id| self.categories.get(id));
+        }
+        lineage.reverse();
+        lineage
+    }
+
+    /// Human-readable path such as "Dairy > Cheese".
+    pub fn path(&self, category_id: &str) -> String {
+        self.lineage(category_id)
+            .iter()
+            .filter_map(|id| self.categories.get(id))
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+
+    pub fn is_within(&self, category_id: &str, ancestor_id: &str) -> bool {
+        self.lineage(category_id).iter().any(|id| id == ancestor_id)
+    }
+
+    /// Nearest shelf life along the lineage, so Cheese inherits from Dairy.
+    pub fn default_shelf_life_days(&self, category_id: &str) -> Option<u32> {
+        self.lineage(category_id)
+            .iter()
+            .rev()
+            .filter_map(|id| self.categories.get(id))
+            .find_map(|c| c.default_shelf_life_days)
+    }
+
+    pub fn set_mapping(&mut self, label: &str, category_id: Option<String>) -> Result<(), InventoryError> {
+        let label = normalize_label(label);
+        if label.is_empty() {
+            return Err(InventoryError::invalid("label", "Mapping label cannot be empty."));
+        }
+        match category_id {
+            Some(category_id) => {
+                if !self.categories.contains_key(&category_id) {
+                    return Err(InventoryError::CategoryNotFound { category_id });
+                }
+                self.mappings.insert(label, category_id);
+            }
+            None => {
+                self.mappings.remove(&label);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves an imported label via the mapping table, then by ID, name or "Parent > Child" path.
+    pub fn resolve(&self, label: &str) -> Option<String> {
+        let label = normalize_label(label);
+        if let Some(category_id) = self.mappings.get(&label) {
+            return Some(category_id.clone());
+        }
+        self.categories
+            .values()
+            .find(|c| {
+                normalize_label(&c.category_id) == label
+                    || normalize_label(&c.name) == label
+                    || normalize_label(&self.path(&c.category_id)) == label
+            })
+            .map(|c| c.category_id.clone())
+    }
+}
@@ -1,55 +1,23 @@
 use candid::{CandidType, Deserialize};
 use ic_cdk_macros::{init, pre_upgrade, post_upgrade, query, update};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
 use serde::Serialize;
 use serde_json::to_string_pretty;
 use chrono::DateTime;
+use xero_types::inventory::{
+    AuditLog, InventoryError, InventoryItem, InventoryItemInput, ItemCategory, ItemDetails, ItemStatus, PackSize,
+    StockLevelChange, StockMovement,
+};
+use xero_types::{Dependencies, Money, Quantity};
 
-// Constants for configuration
-const LOW_STOCK_THRESHOLD: u32 = 10;
-const EXPIRING_SOON_DAYS: u64 = 7;
+mod categories;
+mod migration;
+mod settings;
 
-#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
-pub enum ItemStatus {
-    Active,
-    ExpiringSoon,
-    Expired,
-    LowStock,
-    OutOfStock,
-}
-
-#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
-pub enum ItemCategory {
-    Produce,
-    Dairy,
-    Meat,
-    Bakery,
-    Grocery,
-    Other,
-}
-
-#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
-struct AuditLog {
-    timestamp: u64,
-    action: String,
-    details: String,
-    actor: String,
-}
-
-#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
-pub struct InventoryItem {
-    item_id: String,
-    barcode: String,
-    name: String,
-    category: Option<ItemCategory>,
-    quantity: u32,
-    expiration_date: u64,
-    price: f64,
-    last_updated: u64,
-    status: ItemStatus,
-    audit_trail: Vec<AuditLog>,
-}
+use categories::CategoryRegistry;
+use settings::{InventorySettings, ThresholdOverride, Thresholds};
 
 // Pagination structure for query results
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
@@ -64,18 +32,25 @@ pub struct PaginatedResult {
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct SearchCriteria {
     keyword: Option<String>,
-    category: Option<ItemCategory>,
+    category: Option<String>, // also matches subcategories
     status: Option<ItemStatus>,
-    min_quantity: Option<u32>,
-    max_price: Option<f64>,
+    min_quantity: Option<u32>, // whole units of each item's own unit
+    max_price: Option<Money>, // only items priced in the same currency match
 }
 
-type Inventory = HashMap<String, InventoryItem>;
+type Inventory = BTreeMap<String, InventoryItem>; // ordered by item_id, so listings page stably
 type BarcodeIndex = HashMap<String, String>; // Maps barcode to item_id
 
+const DEFAULT_LIST_LIMIT: usize = 50;
+/// Most items returned by one `list_items_after` call; audit trails make items large.
+const MAX_LIST_LIMIT: usize = 500;
+
 thread_local! {
-    static INVENTORY: RefCell<Inventory> = RefCell::new(HashMap::new());
+    static INVENTORY: RefCell<Inventory> = const { RefCell::new(BTreeMap::new()) };
     static BARCODE_INDEX: RefCell<BarcodeIndex> = RefCell::new(HashMap::new());
+    static SETTINGS: RefCell<InventorySettings> = RefCell::new(InventorySettings::default());
+    static CATEGORIES: RefCell<CategoryRegistry> = RefCell::new(CategoryRegistry::with_defaults());
+    static DEPENDENCIES: RefCell<Dependencies> = RefCell::new(Dependencies::default());
 }
 
 // Helper function to format timestamp as human-readable date
@@ -89,13 +64,43 @@ fn format_timestamp(timestamp: u64) -> String {
 }
 
 #[init]
-fn init() {
+fn init(dependencies: Option<Dependencies>) {
     INVENTORY.with(|inventory| {
-        *inventory.borrow_mut() = HashMap::new();
+        *inventory.borrow_mut() = BTreeMap::new();
     });
     BARCODE_INDEX.with(|index| {
         *index.borrow_mut() = HashMap::new();
     });
+    SETTINGS.with(|settings| {
+        *settings.borrow_mut() = InventorySettings::default();
+    });
+    CATEGORIES.with(|categories| {
+        *categories.borrow_mut() = CategoryRegistry::with_defaults();
+    });
+    DEPENDENCIES.with(|deps| {
+        *deps.borrow_mut() = dependencies.unwrap_or_default();
+    });
+}
+
+// Settings endpoints are restricted to canister controllers
+fn require_admin() -> Result<(), InventoryError> {
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err(InventoryError::Unauthorized { caller })
+    }
+}
+
+// Bulk stock changes are restricted to controllers and the configured data aggregator
+fn require_aggregator() -> Result<(), InventoryError> {
+    let caller = ic_cdk::caller();
+    let aggregator = DEPENDENCIES.with(|deps| deps.borrow().data_aggregator);
+    if ic_cdk::api::is_controller(&caller) || aggregator == Some(caller) {
+        Ok(())
+    } else {
+        Err(InventoryError::Unauthorized { caller })
+    }
 }
 
 #[update]
@@ -103,31 +108,153 @@ fn add_or_update_item(
     item_id: String,
     barcode: String,
     name: String,
-    category: Option<ItemCategory>,
-    quantity: u32,
+    category: Option<String>,
+    quantity: Quantity,
     expiration_date: u64,
-    price: f64,
-) -> Result<String, String> {
+    price: Money,
+) -> Result<String, InventoryError> {
+    upsert_item(InventoryItemInput {
+        item_id,
+        barcode,
+        name,
+        category,
+        quantity,
+        expiration_date,
+        price,
+    })
+}
+
+/// Adds or updates many items at once, e.g. from a data aggregator import; one result per item, in order.
+/// Restricted to controllers and the data aggregator, which validates the items before sending them.
+#[update]
+fn import_items(items: Vec<InventoryItemInput>) -> Result<Vec<Result<String, InventoryError>>, InventoryError> {
+    require_aggregator()?;
+    Ok(items.into_iter().map(upsert_item).collect())
+}
+
+/// Applies sales and stock corrections to existing items; one result per movement, in order.
+/// Restricted to controllers and the data aggregator, which records each change in the ledger.
+/// Stock can't go below zero, so a sale larger than the recorded stock leaves the item out of stock.
+#[update]
+fn apply_stock_movements(
+    movements: Vec<StockMovement>,
+) -> Result<Vec<Result<StockLevelChange, InventoryError>>, InventoryError> {
+    require_aggregator()?;
+    Ok(movements.iter().map(apply_stock_movement).collect())
+}
+
+fn apply_stock_movement(movement: &StockMovement) -> Result<StockLevelChange, InventoryError> {
+    let (quantity, increase, action) = match movement {
+        StockMovement::Sale { quantity, .. } => (quantity, false, "sale"),
+        StockMovement::Adjustment { quantity, increase, .. } => (quantity, *increase, "stock_adjustment"),
+        StockMovement::Disposal { quantity, .. } => (quantity, false, "waste_disposal"),
+    };
+    if quantity.is_zero() || !quantity.is_valid() {
+        return Err(InventoryError::invalid("quantity", "Quantity must be positive and valid for its unit."));
+    }
+
+    let item_id = movement.item_id();
+    INVENTORY.with(|inventory| {
+        let mut inventory = inventory.borrow_mut();
+        let item = inventory
+            .get_mut(item_id)
+            .ok_or_else(|| InventoryError::ItemNotFound { item_id: item_id.to_string() })?;
+        let unit = item.quantity.unit;
+        let delta = quantity.convert_to(unit).ok_or_else(|| {
+            InventoryError::invalid("quantity", &format!("Cannot convert {} to {:?}.", quantity, unit))
+        })?;
+
+        let old_quantity = item.quantity;
+        item.quantity.milli_units = if increase {
+            old_quantity.milli_units.saturating_add(delta.milli_units)
+        } else {
+            old_quantity.milli_units.saturating_sub(delta.milli_units)
+        };
+        if !item.quantity.is_valid() {
+            item.quantity = old_quantity;
+            return Err(InventoryError::invalid("quantity", "Quantity must be whole for items sold each."));
+        }
+
+        let now = ic_cdk::api::time();
+        let thresholds = effective_thresholds(&item.item_id, item.category.as_deref());
+        item.status = determine_item_status(&item.quantity, item.expiration_date, &thresholds);
+        item.last_updated = now;
+        item.audit_trail.push(AuditLog {
+            timestamp: now,
+            action: action.to_string(),
+            details: format!("Quantity changed from {} to {}", old_quantity, item.quantity),
+            actor: ic_cdk::caller().to_text(),
+        });
+        Ok(StockLevelChange {
+            item_id: item.item_id.clone(),
+            old_quantity,
+            new_quantity: item.quantity,
+        })
+    })
+}
+
+fn upsert_item(input: InventoryItemInput) -> Result<String, InventoryError> {
+    let InventoryItemInput {
+        item_id,
+        barcode,
+        name,
+        category,
+        quantity,
+        expiration_date,
+        price,
+    } = input;
+
     if name.trim().is_empty() {
-        return Err("Item name cannot be empty.".to_string());
+        return Err(InventoryError::invalid("name", "Item name cannot be empty."));
     }
     if barcode.trim().is_empty() {
-        return Err("Barcode cannot be empty.".to_string());
+        return Err(InventoryError::invalid("barcode", "Barcode cannot be empty."));
     }
-    if price <= 0.0 {
-        return Err("Price must be greater than zero.".to_string());
+    if price.is_zero() {
+        return Err(InventoryError::invalid("price", "Price must be greater than zero."));
     }
+    if !price.is_valid() {
+        return Err(InventoryError::invalid("price", "Currency must be an ISO 4217 code."));
+    }
+    if !quantity.is_valid() {
+        return Err(InventoryError::invalid("quantity", "Quantity must be whole for items sold each."));
+    }
+    if let Some(category_id) = &category {
+        if CATEGORIES.with(|categories| categories.borrow().get(category_id).is_none()) {
+            return Err(InventoryError::CategoryNotFound { category_id: category_id.clone() });
+        }
+    }
+
+    // A missing expiration date falls back to the category's default shelf life
+    let expiration_date = match (expiration_date, &category) {
+        (0, Some(category_id)) => CATEGORIES
+            .with(|categories| categories.borrow().default_shelf_life_days(category_id))
+            .map_or(0, |days| {
+                ic_cdk::api::time().saturating_add(u64::from(days) * 24 * 60 * 60 * 1_000_000_000)
+            }),
+        _ => expiration_date,
+    };
 
-    let status = determine_item_status(quantity, expiration_date);
+    let thresholds = effective_thresholds(&item_id, category.as_deref());
+    let status = determine_item_status(&quantity, expiration_date, &thresholds);
     let now = ic_cdk::api::time();
     
     let audit_log = AuditLog {
         timestamp: now,
         action: "add_or_update".to_string(),
-        details: format!("Updated quantity: {}, price: {}", quantity, price),
+        details: format!("Updated quantity: {}, price: {} per {}", quantity, price, quantity.unit.symbol()),
         actor: "system".to_string(), // TODO: Implement actual user tracking
     };
 
+    // Pack sizes are managed separately and survive updates
+    let pack_sizes = INVENTORY.with(|inventory| {
+        inventory
+            .borrow()
+            .get(&item_id)
+            .map(|item| item.pack_sizes.clone())
+            .unwrap_or_default()
+    });
+
     let new_item = InventoryItem {
         item_id: item_id.clone(),
         barcode: barcode.clone(),
@@ -139,6 +266,7 @@ fn add_or_update_item(
         last_updated: now,
         status,
         audit_trail: vec![audit_log],
+        pack_sizes,
     };
 
     INVENTORY.with(|inventory| {
@@ -154,18 +282,32 @@ fn add_or_update_item(
     Ok(format!("Item '{}' successfully added or updated.", item_id))
 }
 
-fn determine_item_status(quantity: u32, expiration_date: u64) -> ItemStatus {
+// Reorder points are expressed in whole units of the item's own unit
+fn is_below_reorder_point(quantity: &Quantity, thresholds: &Thresholds) -> bool {
+    quantity.milli_units <= Quantity::whole(u64::from(thresholds.reorder_point), quantity.unit).milli_units
+}
+
+fn effective_thresholds(item_id: &str, category: Option<&str>) -> Thresholds {
+    let lineage = category.map_or_else(Vec::new, |category_id| {
+        CATEGORIES.with(|categories| categories.borrow().lineage(category_id))
+    });
+    SETTINGS.with(|settings| settings.borrow().effective_thresholds(item_id, &lineage))
+}
+
+fn determine_item_status(quantity: &Quantity, expiration_date: u64, thresholds: &Thresholds) -> ItemStatus {
     let now = ic_cdk::api::time();
     
-    if quantity == 0 {
+    if quantity.is_zero() {
         return ItemStatus::OutOfStock;
     }
     
-    if quantity <= LOW_STOCK_THRESHOLD {
+    if is_below_reorder_point(quantity, thresholds) {
         return ItemStatus::LowStock;
     }
     
-    let expiration_threshold = now.saturating_add(EXPIRING_SOON_DAYS * 24 * 60 * 60 * 1_000_000_000);
+    let expiration_threshold = now.saturating_add(
+        thresholds.expiring_soon_days.saturating_mul(24 * 60 * 60 * 1_000_000_000),
+    );
     
     if expiration_date <= now {
         ItemStatus::Expired
@@ -177,23 +319,35 @@ fn determine_item_status(quantity: u32, expiration_date: u64) -> ItemStatus {
 }
 
 #[query]
-fn get_item(id: String) -> Result<String, String> {
+fn get_item(id: String) -> Result<String, InventoryError> {
     INVENTORY.with(|inventory| {
         inventory
             .borrow()
             .get(&id)
             .map(|item| to_string_pretty(item).unwrap_or_default())
-            .ok_or_else(|| format!("Item with ID '{}' not found", id))
+            .ok_or(InventoryError::ItemNotFound { item_id: id })
+    })
+}
+
+/// Typed view of an item for other canisters, e.g. the price engine.
+#[query]
+fn get_item_details(item_id: String) -> Result<ItemDetails, InventoryError> {
+    INVENTORY.with(|inventory| {
+        inventory
+            .borrow()
+            .get(&item_id)
+            .map(ItemDetails::from)
+            .ok_or(InventoryError::ItemNotFound { item_id })
     })
 }
 
 #[query]
-fn get_item_by_barcode(barcode: String) -> Result<String, String> {
+fn get_item_by_barcode(barcode: String) -> Result<String, InventoryError> {
     BARCODE_INDEX.with(|index| {
         if let Some(item_id) = index.borrow().get(&barcode) {
             get_item(item_id.clone())
         } else {
-            Err(format!("Item with barcode '{}' not found", barcode))
+            Err(InventoryError::BarcodeNotFound { barcode })
         }
     })
 }
@@ -223,6 +377,23 @@ fn get_all_items(page: Option<usize>, per_page: Option<usize>) -> PaginatedResul
     })
 }
 
+/// Items in item_id order, starting after the item_id `after`; pass the last item_id of a page back
+/// to get the next one, until a page comes back shorter than `limit`. Unlike `get_all_items` pages,
+/// the cursor doesn't shift when items are added or removed in between.
+#[query]
+fn list_items_after(after: Option<String>, limit: Option<u32>) -> Vec<InventoryItem> {
+    let limit = limit.map_or(DEFAULT_LIST_LIMIT, |l| l as usize).clamp(1, MAX_LIST_LIMIT);
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    INVENTORY.with(|inventory| {
+        inventory
+            .borrow()
+            .range::<String, _>((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(_, item)| item.clone())
+            .collect()
+    })
+}
+
 #[query]
 fn get_all_items_formatted(page: Option<u32>, per_page: Option<u32>) -> String {
     let page = page.map(|p| p as usize);
@@ -231,7 +402,7 @@ fn get_all_items_formatted(page: Option<u32>, per_page: Option<u32>) -> String {
     
     let mut output = format!("\nInventory Items (Page {} of {}, {} items per page)\n",
         result.page,
-        (result.total + result.per_page - 1) / result.per_page,
+        result.total.div_ceil(result.per_page),
         result.per_page);
     output.push_str("=====================================\n\n");
 
@@ -240,9 +411,11 @@ fn get_all_items_formatted(page: Option<u32>, per_page: Option<u32>) -> String {
         output.push_str(&format!("Name: {}\n", item.name));
         output.push_str(&format!("Barcode: {}\n", item.barcode));
         output.push_str(&format!("Category: {}\n", 
-            item.category.map_or("N/A".to_string(), |c| format!("{:?}", c))));
+            item.category.map_or("N/A".to_string(), |c| {
+                CATEGORIES.with(|categories| categories.borrow().path(&c))
+            })));
         output.push_str(&format!("Quantity: {}\n", item.quantity));
-        output.push_str(&format!("Price: ${:.2}\n", item.price));
+        output.push_str(&format!("Price: {} / {}\n", item.price, item.quantity.unit.symbol()));
         output.push_str(&format!("Status: {:?}\n", item.status));
         output.push_str(&format!("Expiration: {}\n", format_timestamp(item.expiration_date)));
         output.push_str(&format!("Last Updated: {}\n", format_timestamp(item.last_updated)));
@@ -259,25 +432,25 @@ fn search_inventory(criteria: SearchCriteria) -> Vec<InventoryItem> {
             .borrow()
             .values()
             .filter(|item| {
-                let keyword_match = criteria.keyword.as_ref().map_or(true, |keyword| {
+                let keyword_match = criteria.keyword.as_ref().is_none_or(|keyword| {
                     item.name.to_lowercase().contains(&keyword.to_lowercase()) ||
                     item.barcode.contains(keyword)
                 });
 
-                let category_match = criteria.category.as_ref().map_or(true, |category| {
-                    item.category.as_ref() == Some(category)
+                let category_match = criteria.category.as_ref().is_none_or(|category| {
+                    item.category.as_ref().is_some_and(|item_category| {
+                        CATEGORIES.with(|categories| categories.borrow().is_within(item_category, category))
+                    })
                 });
 
-                let status_match = criteria.status.as_ref().map_or(true, |_status| {
-                    matches!(&item.status, _status)
-                });
+                let status_match = criteria.status.as_ref().is_none_or(|status| item.status == *status);
 
-                let quantity_match = criteria.min_quantity.map_or(true, |min| {
-                    item.quantity >= min
+                let quantity_match = criteria.min_quantity.is_none_or(|min| {
+                    item.quantity.milli_units >= Quantity::whole(u64::from(min), item.quantity.unit).milli_units
                 });
 
-                let price_match = criteria.max_price.map_or(true, |max| {
-                    item.price <= max
+                let price_match = criteria.max_price.as_ref().is_none_or(|max| {
+                    item.price.currency == max.currency && item.price.minor_units <= max.minor_units
                 });
 
                 keyword_match && category_match && status_match && 
@@ -289,7 +462,7 @@ fn search_inventory(criteria: SearchCriteria) -> Vec<InventoryItem> {
 }
 
 #[update]
-fn remove_item(id: String) -> Result<String, String> {
+fn remove_item(id: String) -> Result<String, InventoryError> {
     INVENTORY.with(|inventory| {
         let mut inventory = inventory.borrow_mut();
         if let Some(item) = inventory.remove(&id) {
@@ -299,7 +472,7 @@ fn remove_item(id: String) -> Result<String, String> {
             });
             Ok(format!("Item '{}' successfully removed", id))
         } else {
-            Err(format!("Item with ID '{}' not found", id))
+            Err(InventoryError::ItemNotFound { item_id: id })
         }
     })
 }
@@ -325,29 +498,248 @@ fn get_low_stock_items() -> Vec<InventoryItem> {
         inventory
             .borrow()
             .values()
-            .filter(|item| item.quantity <= LOW_STOCK_THRESHOLD)
+            .filter(|item| {
+                let thresholds = effective_thresholds(&item.item_id, item.category.as_deref());
+                is_below_reorder_point(&item.quantity, &thresholds)
+            })
             .cloned()
             .collect()
     })
 }
 
+// Recompute stored statuses after thresholds change
+fn refresh_item_statuses() {
+    INVENTORY.with(|inventory| {
+        for item in inventory.borrow_mut().values_mut() {
+            let thresholds = effective_thresholds(&item.item_id, item.category.as_deref());
+            item.status = determine_item_status(&item.quantity, item.expiration_date, &thresholds);
+        }
+    });
+}
+
+#[update]
+fn set_pack_sizes(item_id: String, pack_sizes: Vec<PackSize>) -> Result<String, InventoryError> {
+    INVENTORY.with(|inventory| {
+        let mut inventory = inventory.borrow_mut();
+        let item = inventory
+            .get_mut(&item_id)
+            .ok_or_else(|| InventoryError::ItemNotFound { item_id: item_id.clone() })?;
+
+        for pack in &pack_sizes {
+            if pack.pack_id.trim().is_empty() {
+                return Err(InventoryError::invalid("pack_id", "Pack ID cannot be empty."));
+            }
+            if pack.contains.is_zero() || !pack.contains.is_valid() {
+                return Err(InventoryError::invalid("contains", "Pack must contain a positive, valid quantity."));
+            }
+            if pack.contains.convert_to(item.quantity.unit).is_none() {
+                return Err(InventoryError::invalid("contains", "Pack unit is not convertible to the item's unit."));
+            }
+        }
+
+        item.pack_sizes = pack_sizes;
+        item.last_updated = ic_cdk::api::time();
+        Ok(format!("Pack sizes for item '{}' updated.", item_id))
+    })
+}
+
+/// Converts a number of packs into the item's own unit, e.g. 3 cases of 12 into 36 each.
+#[query]
+fn convert_pack_quantity(item_id: String, pack_id: String, count: u64) -> Result<Quantity, InventoryError> {
+    INVENTORY.with(|inventory| {
+        let inventory = inventory.borrow();
+        let item = inventory
+            .get(&item_id)
+            .ok_or_else(|| InventoryError::ItemNotFound { item_id: item_id.clone() })?;
+        let pack = item
+            .pack_sizes
+            .iter()
+            .find(|pack| pack.pack_id == pack_id)
+            .ok_or_else(|| InventoryError::invalid("pack_id", "Unknown pack size for this item."))?;
+
+        pack.contains
+            .convert_to(item.quantity.unit)
+            .and_then(|per_pack| per_pack.checked_mul(count))
+            .ok_or_else(|| InventoryError::invalid("count", "Pack quantity overflows."))
+    })
+}
+
+#[query]
+fn get_settings() -> InventorySettings {
+    SETTINGS.with(|settings| settings.borrow().clone())
+}
+
+#[query]
+fn get_item_thresholds(item_id: String) -> Result<Thresholds, InventoryError> {
+    INVENTORY.with(|inventory| {
+        inventory
+            .borrow()
+            .get(&item_id)
+            .map(|item| effective_thresholds(&item.item_id, item.category.as_deref()))
+            .ok_or(InventoryError::ItemNotFound { item_id })
+    })
+}
+
+#[update]
+fn set_default_thresholds(thresholds: Thresholds) -> Result<String, InventoryError> {
+    require_admin()?;
+    SETTINGS.with(|settings| settings.borrow_mut().defaults = thresholds);
+    refresh_item_statuses();
+    Ok("Default thresholds updated.".to_string())
+}
+
+/// Passing `None` removes the category override.
+#[update]
+fn set_category_thresholds(
+    category_id: String,
+    thresholds: Option<ThresholdOverride>,
+) -> Result<String, InventoryError> {
+    require_admin()?;
+    if CATEGORIES.with(|categories| categories.borrow().get(&category_id).is_none()) {
+        return Err(InventoryError::CategoryNotFound { category_id });
+    }
+    SETTINGS.with(|settings| {
+        let mut settings = settings.borrow_mut();
+        match thresholds {
+            Some(thresholds) => settings.category_overrides.insert(category_id.clone(), thresholds),
+            None => settings.category_overrides.remove(&category_id),
+        };
+    });
+    refresh_item_statuses();
+    Ok(format!("Thresholds for category '{}' updated.", category_id))
+}
+
+#[query]
+fn list_categories() -> Vec<ItemCategory> {
+    CATEGORIES.with(|categories| categories.borrow().list())
+}
+
+#[query]
+fn get_category(category_id: String) -> Result<ItemCategory, InventoryError> {
+    CATEGORIES.with(|categories| {
+        categories
+            .borrow()
+            .get(&category_id)
+            .cloned()
+            .ok_or(InventoryError::CategoryNotFound { category_id })
+    })
+}
+
+#[update]
+fn upsert_category(category: ItemCategory) -> Result<String, InventoryError> {
+    require_admin()?;
+    let category_id = category.category_id.clone();
+    CATEGORIES.with(|categories| categories.borrow_mut().upsert(category))?;
+    refresh_item_statuses();
+    Ok(format!("Category '{}' saved.", category_id))
+}
+
+#[update]
+fn remove_category(category_id: String) -> Result<String, InventoryError> {
+    require_admin()?;
+    let in_use = INVENTORY.with(|inventory| {
+        inventory
+            .borrow()
+            .values()
+            .any(|item| item.category.as_deref() == Some(category_id.as_str()))
+    });
+    if in_use {
+        return Err(InventoryError::invalid("category_id", "Category is still assigned to items."));
+    }
+    CATEGORIES.with(|categories| categories.borrow_mut().remove(&category_id))?;
+    SETTINGS.with(|settings| settings.borrow_mut().category_overrides.remove(&category_id));
+    Ok(format!("Category '{}' removed.", category_id))
+}
+
+#[query]
+fn list_category_mappings() -> Vec<(String, String)> {
+    CATEGORIES.with(|categories| categories.borrow().mappings())
+}
+
+/// Maps an imported label such as "Fromage" onto a category; `None` removes the mapping.
+#[update]
+fn set_category_mapping(label: String, category_id: Option<String>) -> Result<String, InventoryError> {
+    require_admin()?;
+    CATEGORIES.with(|categories| categories.borrow_mut().set_mapping(&label, category_id))?;
+    Ok(format!("Mapping for '{}' updated.", label))
+}
+
+/// Resolves imported category labels to category IDs, in order; used by data_aggregator.
+#[query]
+fn resolve_categories(labels: Vec<String>) -> Vec<Option<String>> {
+    CATEGORIES.with(|categories| {
+        let categories = categories.borrow();
+        labels.iter().map(|label| categories.resolve(label)).collect()
+    })
+}
+
+/// Passing `None` removes the item override.
+#[update]
+fn set_item_thresholds(
+    item_id: String,
+    thresholds: Option<ThresholdOverride>,
+) -> Result<String, InventoryError> {
+    require_admin()?;
+    if !INVENTORY.with(|inventory| inventory.borrow().contains_key(&item_id)) {
+        return Err(InventoryError::ItemNotFound { item_id });
+    }
+    SETTINGS.with(|settings| {
+        let mut settings = settings.borrow_mut();
+        match thresholds {
+            Some(thresholds) => settings.item_overrides.insert(item_id.clone(), thresholds),
+            None => settings.item_overrides.remove(&item_id),
+        };
+    });
+    refresh_item_statuses();
+    Ok(format!("Thresholds for item '{}' updated.", item_id))
+}
+
+/// Retrieve the IDs of the sibling canisters.
+#[query]
+fn get_dependencies() -> Dependencies {
+    DEPENDENCIES.with(|deps| deps.borrow().clone())
+}
+
+/// Replace the sibling canister IDs.
+#[update]
+fn set_dependencies(dependencies: Dependencies) -> Result<String, InventoryError> {
+    require_admin()?;
+    DEPENDENCIES.with(|deps| *deps.borrow_mut() = dependencies);
+    Ok("Dependencies updated.".to_string())
+}
+
 #[pre_upgrade]
 fn pre_upgrade() {
+    // All state goes into a single tuple; a second stable_save would overwrite the first
     INVENTORY.with(|inventory| {
-        let inventory_data = inventory.borrow();
-        ic_cdk::storage::stable_save((&*inventory_data,)).unwrap();
-    });
-    
-    BARCODE_INDEX.with(|index| {
-        let index_data = index.borrow();
-        ic_cdk::storage::stable_save((&*index_data,)).unwrap();
+        BARCODE_INDEX.with(|index| {
+            SETTINGS.with(|settings| {
+                CATEGORIES.with(|categories| {
+                    DEPENDENCIES.with(|deps| {
+                        ic_cdk::storage::stable_save((
+                            &*inventory.borrow(),
+                            &*index.borrow(),
+                            &*settings.borrow(),
+                            &*categories.borrow(),
+                            &*deps.borrow(),
+                        ))
+                        .unwrap();
+                    });
+                });
+            });
+        });
     });
 }
 
 #[post_upgrade]
 fn post_upgrade() {
-    let (inventory_data,): (Inventory,) = ic_cdk::storage::stable_restore().unwrap();
-    let (index_data,): (BarcodeIndex,) = ic_cdk::storage::stable_restore().unwrap();
+    // State saved in an older layout is migrated; state matching no layout fails the upgrade
+    let bytes = ic_cdk::api::stable::stable_bytes();
+    let ((inventory_data, index_data, settings_data, category_data, dependencies), note) =
+        migration::decode_state(&bytes).unwrap_or_else(|error| ic_cdk::trap(&error));
+    if let Some(note) = note {
+        ic_cdk::println!("{}", note);
+    }
     
     INVENTORY.with(|inventory| {
         *inventory.borrow_mut() = inventory_data;
@@ -356,4 +748,31 @@ fn post_upgrade() {
     BARCODE_INDEX.with(|index| {
         *index.borrow_mut() = index_data;
     });
+
+    SETTINGS.with(|settings| {
+        *settings.borrow_mut() = settings_data;
+    });
+
+    CATEGORIES.with(|categories| {
+        *categories.borrow_mut() = category_data;
+    });
+
+    DEPENDENCIES.with(|deps| {
+        *deps.borrow_mut() = dependencies;
+    });
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use candid::utils::{service_equal, CandidSource};
+    use std::path::Path;
+
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let did_file = Path::new(env!("CARGO_MANIFEST_DIR")).join("inventory.did");
+        service_equal(CandidSource::Text(&super::__export_service()), CandidSource::File(&did_file))
+            .expect("inventory.did is out of date with the Rust interface; run ./generate_did.sh");
+    }
 }
@@ -0,0 +1,134 @@
+//! Decoding of stable state saved by earlier versions of the inventory canister.
+//!
+//! Only two layouts have shipped: the current one, and the first release's, which overwrote its items with
+//! the barcode index. Layouts used while this version was developed were never deployed and aren't decoded.
+
+use candid::de::IDLDeserialize;
+use candid::utils::ArgumentDecoder;
+use xero_types::Dependencies;
+
+use crate::categories::CategoryRegistry;
+use crate::settings::InventorySettings;
+use crate::{BarcodeIndex, Inventory};
+
+/// Everything `pre_upgrade` saves, in order.
+pub type StableState = (Inventory, BarcodeIndex, InventorySettings, CategoryRegistry, Dependencies);
+
+/// Decodes the leading arguments of `bytes`, ignoring the rest as `stable_restore` does;
+/// stable memory keeps whatever an earlier, longer save left after the current one.
+fn decode<T: for<'de> ArgumentDecoder<'de>>(bytes: &[u8]) -> Result<T, String> {
+    let mut de = IDLDeserialize::new(bytes).map_err(|e| e.to_string())?;
+    T::decode(&mut de).map_err(|e| e.to_string())
+}
+
+/// Decodes state saved by this version or the first release. The second value explains anything that
+/// couldn't be carried over; state that matches no known layout is an error, never a fresh start.
+pub fn decode_state(bytes: &[u8]) -> Result<(StableState, Option<String>), String> {
+    let current = match decode::<StableState>(bytes) {
+        Ok(state) => return Ok((state, None)),
+        Err(error) => error,
+    };
+
+    // The first release saved the items and then the barcode index with two `stable_save` calls, the
+    // second overwriting the first. Only the index survives, and it points at items that are gone.
+    if let Ok((index,)) = decode::<(BarcodeIndex,)>(bytes) {
+        let state = (
+            Inventory::new(),
+            BarcodeIndex::new(),
+            InventorySettings::default(),
+            CategoryRegistry::with_defaults(),
+            Dependencies::default(),
+        );
+        let note = format!(
+            "State saved by the first release holds only its barcode index; its items were overwritten \
+             before this upgrade, so the inventory starts empty and {} barcode entries were dropped",
+            index.len()
+        );
+        return Ok((state, Some(note)));
+    }
+
+    Err(format!("Stable state matches no known inventory layout: {}", current))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xero_types::inventory::{InventoryItem, ItemStatus};
+    use xero_types::money::{Money, DEFAULT_CURRENCY};
+    use xero_types::{Quantity, UnitOfMeasure};
+
+    fn item(item_id: &str, quantity: u64, price: u64) -> InventoryItem {
+        InventoryItem {
+            item_id: item_id.to_string(),
+            barcode: format!("{}-barcode", item_id),
+            name: format!("{} name", item_id),
+            category: Some("dairy".to_string()),
+            quantity: Quantity::whole(quantity, UnitOfMeasure::Each),
+            expiration_date: 1_700_000_000_000_000_000,
+            price: Money::new(price, DEFAULT_CURRENCY),
+            last_updated: 1_690_000_000_000_000_000,
+            status: ItemStatus::Active,
+            audit_trail: Vec::new(),
+            pack_sizes: Vec::new(),
+        }
+    }
+
+    fn inventory() -> Inventory {
+        [item("milk", 42, 129), item("apples", 7, 50)]
+            .into_iter()
+            .map(|item| (item.item_id.clone(), item))
+            .collect()
+    }
+
+    fn index_for<'a>(item_ids: impl Iterator<Item = &'a String>) -> BarcodeIndex {
+        item_ids.map(|item_id| (format!("{}-barcode", item_id), item_id.clone())).collect()
+    }
+
+    #[test]
+    fn first_release_state_starts_empty_with_a_note() {
+        // The first release's pre_upgrade: save the items, then save the index over them
+        let items = inventory();
+        let mut bytes = candid::encode_args((&items,)).unwrap();
+        let index_bytes = candid::encode_args((&index_for(items.keys()),)).unwrap();
+        assert!(index_bytes.len() < bytes.len());
+        bytes[..index_bytes.len()].copy_from_slice(&index_bytes);
+
+        let ((inventory, index, settings, categories, dependencies), note) = decode_state(&bytes).unwrap();
+        assert!(inventory.is_empty());
+        assert!(index.is_empty());
+        assert!(settings.item_overrides.is_empty());
+        assert_eq!(categories.list().len(), CategoryRegistry::with_defaults().list().len());
+        assert!(dependencies.inventory.is_none());
+        assert!(note.unwrap().contains("2 barcode entries were dropped"));
+    }
+
+    #[test]
+    fn current_state_round_trips() {
+        let inventory = inventory();
+        let dependencies = Dependencies {
+            inventory: Some(candid::Principal::anonymous()),
+            ..Dependencies::default()
+        };
+        let bytes = candid::encode_args((
+            &inventory,
+            &index_for(inventory.keys()),
+            &InventorySettings::default(),
+            &CategoryRegistry::with_defaults(),
+            &dependencies,
+        ))
+        .unwrap();
+
+        let ((restored, index, _, _, restored_dependencies), note) = decode_state(&bytes).unwrap();
+        assert!(note.is_none());
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored["milk"].price, Money::new(129, DEFAULT_CURRENCY));
+        assert_eq!(index.get("milk-barcode").map(String::as_str), Some("milk"));
+        assert_eq!(restored_dependencies.inventory, dependencies.inventory);
+    }
+
+    #[test]
+    fn unknown_state_is_an_error() {
+        assert!(decode_state(b"not candid").is_err());
+        assert!(decode_state(&candid::encode_args((42u64,)).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Defaults used until an admin configures thresholds
+const LOW_STOCK_THRESHOLD: u32 = 10;
+const EXPIRING_SOON_DAYS: u64 = 7;
+
+/// Thresholds used to derive an item's status.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Thresholds {
+    pub reorder_point: u32,
+    pub expiring_soon_days: u64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            reorder_point: LOW_STOCK_THRESHOLD,
+            expiring_soon_days: EXPIRING_SOON_DAYS,
+        }
+    }
+}
+
+/// Partial thresholds; unset fields fall through to the next level.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
+pub struct ThresholdOverride {
+    pub reorder_point: Option<u32>,
+    pub expiring_soon_days: Option<u64>,
+}
+
+impl ThresholdOverride {
+    fn apply_to(&self, thresholds: &mut Thresholds) {
+        if let Some(reorder_point) = self.reorder_point {
+            thresholds.reorder_point = reorder_point;
+        }
+        if let Some(days) = self.expiring_soon_days {
+            thresholds.expiring_soon_days = days;
+        }
+    }
+}
+
+/// Threshold configuration, resolved as item override > category overrides > defaults.
+/// Category overrides apply from the root category down, so Cheese refines Dairy.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct InventorySettings {
+    pub defaults: Thresholds,
+    pub category_overrides: HashMap<String, ThresholdOverride>,
+    pub item_overrides: HashMap<String, ThresholdOverride>,
+}
+
+impl InventorySettings {
+    pub fn effective_thresholds(&self, item_id: &str, category_lineage: &[String]) -> Thresholds {
+        let mut thresholds = self.defaults.clone();
+        for category_id in category_lineage {
+            if let Some(category_override) = self.category_overrides.get(category_id) {
+                category_override.apply_to(&mut thresholds);
+            }
+        }
+        if let Some(item_override) = self.item_overrides.get(item_id) {
+            item_override.apply_to(&mut thresholds);
+        }
+        thresholds
+    }
+}
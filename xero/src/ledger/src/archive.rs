@@ -0,0 +1,192 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::{
+    create_canister, install_code, CanisterInstallMode, CanisterSettings, CreateCanisterArgument,
+    InstallCodeArgument,
+};
+use xero_types::{CallFailure, Transaction};
+
+use crate::{ARCHIVE_STATE, FIRST_LOCAL_INDEX, LEDGER, SECONDARY_INDEXES};
+
+/// When and how the ledger moves old blocks into archive canisters.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchiveOptions {
+    pub trigger_threshold: u64, // archive once this many blocks are held locally
+    pub num_blocks_to_archive: u64, // blocks moved per archiving round
+    pub max_blocks_per_archive: u64, // a new archive is spawned when the last one is full
+    pub cycles_for_archive_creation: u64,
+    pub controllers: Vec<Principal>, // extra archive controllers besides the ledger itself
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        ArchiveOptions {
+            trigger_threshold: 2_000,
+            num_blocks_to_archive: 1_000,
+            max_blocks_per_archive: 1_000_000,
+            cycles_for_archive_creation: 2_000_000_000_000,
+            controllers: Vec::new(),
+        }
+    }
+}
+
+/// An archive canister and the half-open range of block IDs it holds.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchiveInfo {
+    pub canister_id: Principal,
+    pub block_range_start: u64,
+    pub block_range_end: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ArchiveState {
+    pub options: ArchiveOptions,
+    pub archives: Vec<ArchiveInfo>,
+    pub wasm: Option<Vec<u8>>, // module installed into new archives, uploaded via `set_archive_wasm`
+    /// Archive created by a round whose `install_code` failed; the next round installs into it instead of
+    /// creating (and paying for) another canister.
+    pub uninstalled_archive: Option<Principal>,
+}
+
+impl ArchiveState {
+    /// Archive holding `transaction_id`, if it has been moved out of the ledger.
+    pub fn archive_for(&self, transaction_id: u64) -> Option<&ArchiveInfo> {
+        self.archives
+            .iter()
+            .find(|a| a.block_range_start <= transaction_id && transaction_id < a.block_range_end)
+    }
+}
+
+/// Kicks off an archiving round in the background if the ledger has grown past the threshold.
+pub fn schedule() {
+    let threshold = ARCHIVE_STATE.with(|state| state.borrow().options.trigger_threshold);
+    let local_blocks = LEDGER.with(|ledger| ledger.borrow().len() as u64);
+    if local_blocks >= threshold && !crate::ARCHIVING.with(|archiving| *archiving.borrow()) {
+        ic_cdk::spawn(archive_blocks());
+    }
+}
+
+pub async fn archive_blocks() {
+    // Only one round runs at a time; blocks appended meanwhile wait for the next round
+    if crate::ARCHIVING.with(|archiving| archiving.replace(true)) {
+        return;
+    }
+    if let Err(e) = move_oldest_blocks().await {
+        ic_cdk::println!("Ledger archiving failed: {}", e);
+    }
+    crate::ARCHIVING.with(|archiving| *archiving.borrow_mut() = false);
+}
+
+async fn move_oldest_blocks() -> Result<(), String> {
+    let options = ARCHIVE_STATE.with(|state| state.borrow().options.clone());
+    let local_blocks = LEDGER.with(|ledger| ledger.borrow().len() as u64);
+    if local_blocks < options.trigger_threshold {
+        return Ok(());
+    }
+
+    let mut remaining = options.num_blocks_to_archive.min(local_blocks);
+    while remaining > 0 {
+        let (archive_id, capacity) = archive_with_capacity(&options).await?;
+        let chunk_len = remaining.min(capacity) as usize;
+        let chunk: Vec<Transaction> = LEDGER.with(|ledger| ledger.borrow()[..chunk_len].to_vec());
+
+        let (result,): (Result<u64, candid::Reserved>,) =
+            ic_cdk::api::call::call(archive_id, "append_transactions", (chunk,))
+                .await
+                .map_err(|e| CallFailure::new(archive_id, "append_transactions", e).to_string())?;
+        result.map_err(|_| format!("Archive {} refused the blocks", archive_id))?;
+
+        drop_archived_blocks(chunk_len, archive_id);
+        remaining -= chunk_len as u64;
+    }
+    Ok(())
+}
+
+// Returns the newest archive if it still has room, spawning a fresh one otherwise
+async fn archive_with_capacity(options: &ArchiveOptions) -> Result<(Principal, u64), String> {
+    let last = ARCHIVE_STATE.with(|state| state.borrow().archives.last().cloned());
+    if let Some(archive) = last {
+        let held = archive.block_range_end - archive.block_range_start;
+        if held < options.max_blocks_per_archive {
+            return Ok((archive.canister_id, options.max_blocks_per_archive - held));
+        }
+    }
+
+    let first_index = FIRST_LOCAL_INDEX.with(|index| *index.borrow());
+    let canister_id = spawn_archive(first_index, options).await?;
+    ARCHIVE_STATE.with(|state| {
+        state.borrow_mut().archives.push(ArchiveInfo {
+            canister_id,
+            block_range_start: first_index,
+            block_range_end: first_index,
+        });
+    });
+    Ok((canister_id, options.max_blocks_per_archive))
+}
+
+async fn spawn_archive(first_index: u64, options: &ArchiveOptions) -> Result<Principal, String> {
+    let wasm_module = ARCHIVE_STATE
+        .with(|state| state.borrow().wasm.clone())
+        .ok_or("Archive wasm has not been uploaded")?;
+
+    let canister_id = match ARCHIVE_STATE.with(|state| state.borrow().uninstalled_archive) {
+        Some(canister_id) => canister_id,
+        None => {
+            let canister_id = create_archive_canister(options).await?;
+            ARCHIVE_STATE.with(|state| state.borrow_mut().uninstalled_archive = Some(canister_id));
+            canister_id
+        }
+    };
+
+    let arg = candid::encode_args((ic_cdk::id(), first_index)).map_err(|e| e.to_string())?;
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id,
+        wasm_module,
+        arg,
+    })
+    .await
+    .map_err(|e| CallFailure::new(Principal::management_canister(), "install_code", e).to_string())?;
+
+    ARCHIVE_STATE.with(|state| state.borrow_mut().uninstalled_archive = None);
+    Ok(canister_id)
+}
+
+async fn create_archive_canister(options: &ArchiveOptions) -> Result<Principal, String> {
+    let mut controllers = options.controllers.clone();
+    controllers.push(ic_cdk::id());
+    let (record,) = create_canister(
+        CreateCanisterArgument {
+            settings: Some(CanisterSettings {
+                controllers: Some(controllers),
+                ..Default::default()
+            }),
+        },
+        u128::from(options.cycles_for_archive_creation),
+    )
+    .await
+    .map_err(|e| CallFailure::new(Principal::management_canister(), "create_canister", e).to_string())?;
+    Ok(record.canister_id)
+}
+
+fn drop_archived_blocks(count: usize, archive_id: Principal) {
+    LEDGER.with(|ledger| {
+        ledger.borrow_mut().drain(..count);
+    });
+    let first_local_index = FIRST_LOCAL_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        *index += count as u64;
+        *index
+    });
+
+    SECONDARY_INDEXES.with(|indexes| indexes.borrow_mut().prune_before(first_local_index));
+    ARCHIVE_STATE.with(|state| {
+        if let Some(archive) = state
+            .borrow_mut()
+            .archives
+            .iter_mut()
+            .find(|a| a.canister_id == archive_id)
+        {
+            archive.block_range_end = first_local_index;
+        }
+    });
+}
@@ -0,0 +1,105 @@
+use candid::{CandidType, Deserialize};
+use std::fmt::Write;
+use xero_types::Transaction;
+
+use crate::{index, LedgerError, TransactionQuery, FIRST_LOCAL_INDEX, LEDGER, SECONDARY_INDEXES};
+
+/// Chunks stop growing once they pass this many bytes, keeping each response well under the message limit.
+const CHUNK_BYTES: usize = 1_000_000;
+
+const CSV_HEADER: &str = "transaction_id,timestamp,action_type,actor_id,item_ids,details,idempotency_key\n";
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+}
+
+/// One chunk of an export; pass `next_cursor` back as the query's `cursor` to get the next one.
+/// The CSV header is only written in the first chunk, so chunks can be concatenated as they arrive.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExportChunk {
+    pub content: String,
+    pub next_cursor: Option<u64>,
+}
+
+/// Renders the locally held transactions matching `query`, starting at its cursor.
+/// `query.limit` caps the rows in the chunk; without it the chunk is filled up to `CHUNK_BYTES`.
+/// Chunks needing archived blocks, as judged by `index::page_reaches_archive`, fail instead of producing an
+/// export that silently misses them.
+pub fn export_chunk(query: &TransactionQuery, format: ExportFormat) -> Result<ExportChunk, LedgerError> {
+    let max_rows = query.limit.map_or(usize::MAX, |limit| limit.max(1) as usize);
+    let first_index = FIRST_LOCAL_INDEX.with(|index| *index.borrow());
+
+    let mut content = String::new();
+    if format == ExportFormat::Csv && query.cursor.is_none() {
+        content.push_str(CSV_HEADER);
+    }
+
+    let mut rows = 0;
+    let mut cursor = query.cursor;
+    LEDGER.with(|ledger| {
+        SECONDARY_INDEXES.with(|indexes| loop {
+            let page_query = TransactionQuery {
+                cursor,
+                limit: Some((max_rows - rows).min(u32::MAX as usize) as u32),
+                ..query.clone()
+            };
+            let page = index::run_query(&ledger.borrow(), first_index, &indexes.borrow(), &page_query);
+            for transaction in &page.transactions {
+                write_row(&mut content, transaction, format);
+            }
+            rows += page.transactions.len();
+            cursor = page.next_cursor;
+            if cursor.is_none() || rows >= max_rows || content.len() >= CHUNK_BYTES {
+                break;
+            }
+        })
+    });
+
+    if LEDGER.with(|ledger| index::page_reaches_archive(&ledger.borrow(), first_index, query, cursor)) {
+        return Err(LedgerError::RangeArchived { first_index });
+    }
+    Ok(ExportChunk { content, next_cursor: cursor })
+}
+
+fn write_row(out: &mut String, transaction: &Transaction, format: ExportFormat) {
+    match format {
+        ExportFormat::Csv => {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                transaction.transaction_id,
+                transaction.timestamp,
+                csv_field(&transaction.action_type),
+                csv_field(&transaction.actor_id),
+                csv_field(&transaction.item_ids.join(";")),
+                csv_field(&transaction.details),
+                csv_field(transaction.idempotency_key.as_deref().unwrap_or("")),
+            );
+        }
+        ExportFormat::Jsonl => {
+            // Serializing plain data into a String can't fail
+            out.push_str(&serde_json::to_string(transaction).unwrap_or_default());
+            out.push('\n');
+        }
+    }
+}
+
+// Quotes the field when it contains a delimiter, quote or line break (RFC 4180)
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
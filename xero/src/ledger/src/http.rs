@@ -0,0 +1,180 @@
+use candid::{CandidType, Deserialize};
+
+use crate::export::{export_chunk, ExportFormat};
+use crate::{LedgerError, SortOrder, TransactionQuery};
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub streaming_strategy: Option<StreamingStrategy>,
+}
+
+/// Where the HTTP gateway picks up the rest of an export: the original URL plus the next cursor.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StreamingToken {
+    pub url: String,
+    pub cursor: u64,
+}
+
+candid::define_function!(pub StreamingCallback : (StreamingToken) -> (StreamingCallbackHttpResponse) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum StreamingStrategy {
+    Callback { callback: StreamingCallback, token: StreamingToken },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: Vec<u8>,
+    pub token: Option<StreamingToken>,
+}
+
+/// Serves `GET /export.csv` and `GET /export.jsonl`.
+/// Query parameters: `from` and `to` (inclusive, nanoseconds since the epoch), `action_type`, `actor_id`,
+/// `item_id` and `order=newest`. Exports larger than one chunk are streamed through the callback.
+/// Ranges reaching archived blocks get a 409 rather than a partial export.
+pub fn handle(request: &HttpRequest) -> HttpResponse {
+    if !request.method.eq_ignore_ascii_case("GET") {
+        return error_response(405, "Only GET is supported");
+    }
+    let (format, query) = match parse_export_url(&request.url) {
+        Ok(parsed) => parsed,
+        Err((status, message)) => return error_response(status, &message),
+    };
+
+    let chunk = match export_chunk(&query, format) {
+        Ok(chunk) => chunk,
+        Err(e) => return error_response(409, &export_error_message(&e)),
+    };
+    let filename = match format {
+        ExportFormat::Csv => "ledger-export.csv",
+        ExportFormat::Jsonl => "ledger-export.jsonl",
+    };
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), format.content_type().to_string()),
+            (
+                "Content-Disposition".to_string(),
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body: chunk.content.into_bytes(),
+        streaming_strategy: chunk.next_cursor.map(|cursor| streaming_strategy(&request.url, cursor)),
+    }
+}
+
+/// Produces the chunk after `token` for an export started by `handle`.
+pub fn next_chunk(token: StreamingToken) -> StreamingCallbackHttpResponse {
+    let (format, mut query) = match parse_export_url(&token.url) {
+        Ok(parsed) => parsed,
+        Err(_) => return StreamingCallbackHttpResponse { body: Vec::new(), token: None },
+    };
+    query.cursor = Some(token.cursor);
+
+    // Blocks archived mid-download; trapping fails the download instead of truncating it
+    let chunk = export_chunk(&query, format).unwrap_or_else(|e| ic_cdk::trap(&export_error_message(&e)));
+    StreamingCallbackHttpResponse {
+        body: chunk.content.into_bytes(),
+        token: chunk.next_cursor.map(|cursor| StreamingToken { url: token.url, cursor }),
+    }
+}
+
+fn streaming_strategy(url: &str, cursor: u64) -> StreamingStrategy {
+    StreamingStrategy::Callback {
+        callback: StreamingCallback::new(ic_cdk::id(), "http_request_streaming_callback".to_string()),
+        token: StreamingToken { url: url.to_string(), cursor },
+    }
+}
+
+fn export_error_message(error: &LedgerError) -> String {
+    match error {
+        LedgerError::RangeArchived { first_index } => format!(
+            "Transactions before {} have been archived and can't be exported over HTTP; \
+             pass a later 'from' or read them through get_transactions",
+            first_index
+        ),
+        other => format!("{:?}", other),
+    }
+}
+
+fn error_response(status_code: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())],
+        body: message.as_bytes().to_vec(),
+        streaming_strategy: None,
+    }
+}
+
+fn parse_export_url(url: &str) -> Result<(ExportFormat, TransactionQuery), (u16, String)> {
+    let (path, query_string) = url.split_once('?').unwrap_or((url, ""));
+    let format = match path {
+        "/export.csv" => ExportFormat::Csv,
+        "/export.jsonl" => ExportFormat::Jsonl,
+        _ => return Err((404, format!("Not found: {}", path))),
+    };
+
+    let mut query = TransactionQuery::default();
+    for pair in query_string.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value).ok_or_else(|| (400, format!("Malformed value for '{}'", key)))?;
+        match key {
+            "from" => query.start_time = Some(parse_timestamp(key, &value)?),
+            "to" => query.end_time = Some(parse_timestamp(key, &value)?),
+            "action_type" => query.action_type = Some(value),
+            "actor_id" => query.actor_id = Some(value),
+            "item_id" => query.item_id = Some(value),
+            "order" => {
+                query.order = Some(match value.as_str() {
+                    "oldest" => SortOrder::OldestFirst,
+                    "newest" => SortOrder::NewestFirst,
+                    _ => return Err((400, "order must be 'oldest' or 'newest'".to_string())),
+                })
+            }
+            _ => return Err((400, format!("Unknown parameter '{}'", key))),
+        }
+    }
+    Ok((format, query))
+}
+
+fn parse_timestamp(key: &str, value: &str) -> Result<u64, (u16, String)> {
+    value
+        .parse()
+        .map_err(|_| (400, format!("'{}' must be a timestamp in nanoseconds", key)))
+}
+
+// Decodes `%XX` escapes and `+` as space; `None` for truncated escapes or invalid UTF-8
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,123 @@
+use candid::{CandidType, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use xero_types::{Transaction, TransactionKind};
+
+/// How long a used idempotency key is remembered, whether or not its block has been archived since.
+pub const WINDOW_NANOS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A key's transaction, and enough of it to tell a retry from a different request once the block is archived.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct UsedKey {
+    pub transaction_id: u64,
+    pub recorded_at: u64,
+    fingerprint: Vec<u8>, // SHA-256 of the Candid-encoded actor and kind
+}
+
+impl UsedKey {
+    /// Whether a request with this actor and kind repeats the one that used the key.
+    pub fn matches(&self, actor_id: &str, kind: &TransactionKind) -> bool {
+        self.fingerprint == fingerprint(actor_id, kind)
+    }
+}
+
+/// Idempotency keys used within the last `WINDOW_NANOS`, held apart from the blocks so archiving doesn't
+/// forget them.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct IdempotencyKeys {
+    keys: HashMap<String, UsedKey>,
+    order: VecDeque<String>, // oldest first
+}
+
+impl IdempotencyKeys {
+    /// Keys of the given blocks still inside the window; for state saved before keys were kept apart.
+    pub fn from_blocks(blocks: &[Transaction], now: u64) -> Self {
+        let mut keys = IdempotencyKeys::default();
+        for transaction in blocks {
+            keys.insert(transaction);
+        }
+        keys.prune(now);
+        keys
+    }
+
+    pub fn get(&self, key: &str) -> Option<&UsedKey> {
+        self.keys.get(key)
+    }
+
+    /// Remembers the key of a newly appended transaction, if it has one.
+    pub fn insert(&mut self, transaction: &Transaction) {
+        let Some(key) = &transaction.idempotency_key else {
+            return;
+        };
+        let used = UsedKey {
+            transaction_id: transaction.transaction_id,
+            recorded_at: transaction.timestamp,
+            fingerprint: fingerprint(&transaction.actor_id, &transaction.kind),
+        };
+        if self.keys.insert(key.clone(), used).is_none() {
+            self.order.push_back(key.clone());
+        }
+    }
+
+    /// Forgets the keys used more than `WINDOW_NANOS` before `now`.
+    pub fn prune(&mut self, now: u64) {
+        while let Some(key) = self.order.front() {
+            if self.keys[key].recorded_at.saturating_add(WINDOW_NANOS) > now {
+                break;
+            }
+            self.keys.remove(key);
+            self.order.pop_front();
+        }
+    }
+}
+
+fn fingerprint(actor_id: &str, kind: &TransactionKind) -> Vec<u8> {
+    // Encoding plain data can't fail
+    let encoded = candid::encode_args((actor_id, kind)).unwrap_or_default();
+    Sha256::digest(encoded).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(transaction_id: u64, timestamp: u64, key: &str, actor_id: &str) -> Transaction {
+        let details = "restock".to_string();
+        Transaction {
+            transaction_id,
+            timestamp,
+            action_type: "legacy".to_string(),
+            details: details.clone(),
+            actor_id: actor_id.to_string(),
+            item_ids: Vec::new(),
+            kind: TransactionKind::Legacy { details },
+            idempotency_key: Some(key.to_string()),
+        }
+    }
+
+    #[test]
+    fn keys_are_kept_for_the_window_only() {
+        let mut keys = IdempotencyKeys::default();
+        keys.insert(&transaction(0, 0, "a", "aggregator"));
+        keys.insert(&transaction(1, WINDOW_NANOS / 2, "b", "aggregator"));
+
+        keys.prune(WINDOW_NANOS - 1);
+        let used = keys.get("a").unwrap();
+        assert_eq!(used.transaction_id, 0);
+        let kind = TransactionKind::Legacy { details: "restock".to_string() };
+        assert!(used.matches("aggregator", &kind));
+        assert!(!used.matches("price_engine", &kind));
+
+        keys.prune(WINDOW_NANOS);
+        assert!(keys.get("a").is_none());
+        assert_eq!(keys.get("b").map(|used| used.transaction_id), Some(1));
+    }
+
+    #[test]
+    fn rebuilding_skips_keys_past_the_window() {
+        let blocks = [transaction(4, 0, "old", "aggregator"), transaction(5, WINDOW_NANOS, "new", "aggregator")];
+        let keys = IdempotencyKeys::from_blocks(&blocks, WINDOW_NANOS + 1);
+        assert!(keys.get("old").is_none());
+        assert!(keys.get("new").is_some());
+    }
+}
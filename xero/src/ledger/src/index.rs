@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crate::{SortOrder, Transaction, TransactionPage, TransactionQuery};
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Transaction IDs of locally held blocks grouped by the fields queries filter on; each list is kept in ledger order.
+#[derive(Default)]
+pub struct SecondaryIndexes {
+    by_action_type: HashMap<String, Vec<u64>>,
+    by_actor: HashMap<String, Vec<u64>>,
+    by_item: HashMap<String, Vec<u64>>,
+}
+
+impl SecondaryIndexes {
+    pub fn rebuild(ledger: &[Transaction]) -> Self {
+        let mut indexes = SecondaryIndexes::default();
+        for transaction in ledger {
+            indexes.insert(transaction);
+        }
+        indexes
+    }
+
+    pub fn insert(&mut self, transaction: &Transaction) {
+        let id = transaction.transaction_id;
+        self.by_action_type
+            .entry(transaction.action_type.clone())
+            .or_default()
+            .push(id);
+        self.by_actor.entry(transaction.actor_id.clone()).or_default().push(id);
+        for item_id in &transaction.item_ids {
+            self.by_item.entry(item_id.clone()).or_default().push(id);
+        }
+    }
+
+    /// Drops every ID below `first_index`, once those blocks have moved to an archive.
+    pub fn prune_before(&mut self, first_index: u64) {
+        for index in [&mut self.by_action_type, &mut self.by_actor, &mut self.by_item] {
+            index.retain(|_, ids| {
+                let archived = ids.partition_point(|&id| id < first_index);
+                ids.drain(..archived);
+                !ids.is_empty()
+            });
+        }
+    }
+
+    // Shortest posting list among the indexed filters; `None` when the query has none of them
+    fn candidates(&self, query: &TransactionQuery) -> Option<&[u64]> {
+        let lookups = [
+            (&self.by_action_type, &query.action_type),
+            (&self.by_actor, &query.actor_id),
+            (&self.by_item, &query.item_id),
+        ];
+        lookups
+            .into_iter()
+            .filter_map(|(index, key)| {
+                key.as_ref()
+                    .map(|key| index.get(key).map_or(&[][..], |ids| ids.as_slice()))
+            })
+            .min_by_key(|ids| ids.len())
+    }
+}
+
+fn matches(transaction: &Transaction, query: &TransactionQuery) -> bool {
+    query.action_type.as_ref().is_none_or(|a| &transaction.action_type == a)
+        && query.actor_id.as_ref().is_none_or(|a| &transaction.actor_id == a)
+        && query.item_id.as_ref().is_none_or(|i| transaction.item_ids.contains(i))
+}
+
+/// Whether answering `query` needed blocks below `first_index`, which have moved to archives, given the
+/// `next_cursor` its page ended with. That is an oldest-first query starting before the oldest local block,
+/// or a newest-first one that ran out of local blocks while its range reaches further back; a newest-first
+/// page filled from local blocks alone, such as the latest N transactions, never does.
+pub fn page_reaches_archive(
+    ledger: &[Transaction],
+    first_index: u64,
+    query: &TransactionQuery,
+    next_cursor: Option<u64>,
+) -> bool {
+    if matches!(query.order, Some(SortOrder::NewestFirst)) {
+        return next_cursor.is_none() && period_reaches_archive(ledger, first_index, query.start_time);
+    }
+    match query.cursor {
+        Some(cursor) => cursor < first_index,
+        None => period_reaches_archive(ledger, first_index, query.start_time),
+    }
+}
+
+/// Whether transactions from `start_time` on include archived ones; the archives hold everything before `ledger[0]`.
+pub fn period_reaches_archive(ledger: &[Transaction], first_index: u64, start_time: Option<u64>) -> bool {
+    first_index > 0
+        && match (start_time, ledger.first()) {
+            (Some(start), Some(oldest)) => start < oldest.timestamp,
+            _ => true,
+        }
+}
+
+/// Runs `query` over the locally held blocks; `first_index` is the ID of `ledger[0]`.
+pub fn run_query(
+    ledger: &[Transaction],
+    first_index: u64,
+    indexes: &SecondaryIndexes,
+    query: &TransactionQuery,
+) -> TransactionPage {
+    let limit = query.limit.map_or(DEFAULT_PAGE_SIZE, |l| l as usize).clamp(1, MAX_PAGE_SIZE);
+    let descending = matches!(query.order, Some(SortOrder::NewestFirst));
+
+    // Timestamps only grow, so a time range is a contiguous span of IDs
+    let mut lo = first_index
+        + query.start_time.map_or(0, |start| ledger.partition_point(|t| t.timestamp < start)) as u64;
+    let mut hi = first_index
+        + query
+            .end_time
+            .map_or(ledger.len(), |end| ledger.partition_point(|t| t.timestamp <= end)) as u64;
+    if let Some(cursor) = query.cursor {
+        if descending {
+            hi = hi.min(cursor.saturating_add(1));
+        } else {
+            lo = lo.max(cursor);
+        }
+    }
+    if lo >= hi {
+        return TransactionPage { transactions: Vec::new(), next_cursor: None };
+    }
+
+    let ids: Box<dyn DoubleEndedIterator<Item = u64>> = match indexes.candidates(query) {
+        Some(list) => {
+            let start = list.partition_point(|&id| id < lo);
+            let end = list.partition_point(|&id| id < hi);
+            Box::new(list[start..end].iter().copied())
+        }
+        None => Box::new(lo..hi),
+    };
+    let ids: Box<dyn Iterator<Item = u64>> = if descending { Box::new(ids.rev()) } else { ids };
+    let block = |id: u64| &ledger[(id - first_index) as usize];
+
+    // Fetch one extra match to learn where the next page starts
+    let mut page: Vec<u64> = ids.filter(|&id| matches(block(id), query)).take(limit + 1).collect();
+    let next_cursor = if page.len() > limit { page.pop() } else { None };
+
+    TransactionPage {
+        transactions: page.into_iter().map(|id| block(id).clone()).collect(),
+        next_cursor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xero_types::TransactionKind;
+
+    fn ledger(ids: std::ops::Range<u64>) -> Vec<Transaction> {
+        ids.map(|id| Transaction {
+            transaction_id: id,
+            timestamp: 100 * id,
+            action_type: "Legacy".to_string(),
+            details: String::new(),
+            actor_id: if id % 2 == 0 { "alice" } else { "bob" }.to_string(),
+            item_ids: Vec::new(),
+            kind: TransactionKind::Legacy { details: String::new() },
+            idempotency_key: None,
+        })
+        .collect()
+    }
+
+    // Follows `next_cursor` to the end, returning the IDs on each page
+    fn pages(ledger: &[Transaction], first_index: u64, query: TransactionQuery) -> Vec<Vec<u64>> {
+        let indexes = SecondaryIndexes::rebuild(ledger);
+        let mut query = query;
+        let mut pages = Vec::new();
+        loop {
+            let page = run_query(ledger, first_index, &indexes, &query);
+            pages.push(page.transactions.iter().map(|t| t.transaction_id).collect());
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return pages,
+            }
+        }
+    }
+
+    fn query(order: SortOrder, limit: u32) -> TransactionQuery {
+        TransactionQuery {
+            order: Some(order),
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pages_cover_every_block_once_in_both_orders() {
+        let ledger = ledger(0..10);
+        assert_eq!(
+            pages(&ledger, 0, query(SortOrder::OldestFirst, 4)),
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]
+        );
+        assert_eq!(
+            pages(&ledger, 0, query(SortOrder::NewestFirst, 4)),
+            vec![vec![9, 8, 7, 6], vec![5, 4, 3, 2], vec![1, 0]]
+        );
+        // An exact multiple of the limit doesn't leave an empty trailing page
+        assert_eq!(pages(&ledger, 0, query(SortOrder::OldestFirst, 5)).len(), 2);
+    }
+
+    #[test]
+    fn indexed_filters_page_through_matches_only() {
+        let ledger = ledger(0..10);
+        let by_alice = |order| TransactionQuery {
+            actor_id: Some("alice".to_string()),
+            ..query(order, 2)
+        };
+        assert_eq!(
+            pages(&ledger, 0, by_alice(SortOrder::OldestFirst)),
+            vec![vec![0, 2], vec![4, 6], vec![8]]
+        );
+        assert_eq!(
+            pages(&ledger, 0, by_alice(SortOrder::NewestFirst)),
+            vec![vec![8, 6], vec![4, 2], vec![0]]
+        );
+
+        let window = TransactionQuery {
+            start_time: Some(250),
+            end_time: Some(700),
+            ..by_alice(SortOrder::OldestFirst)
+        };
+        assert_eq!(pages(&ledger, 0, window), vec![vec![4, 6]]);
+    }
+
+    #[test]
+    fn paging_resumes_after_older_blocks_are_pruned() {
+        let full = ledger(0..10);
+        let mut indexes = SecondaryIndexes::rebuild(&full);
+        let first = run_query(&full, 0, &indexes, &query(SortOrder::OldestFirst, 3));
+        assert_eq!(first.next_cursor, Some(3));
+
+        // Blocks 0..5 move to an archive; a cursor still held locally keeps working
+        indexes.prune_before(5);
+        let local = &full[5..];
+        let resumed = TransactionQuery {
+            cursor: Some(6),
+            ..query(SortOrder::OldestFirst, 3)
+        };
+        let page = run_query(local, 5, &indexes, &resumed);
+        assert!(!page_reaches_archive(local, 5, &resumed, page.next_cursor));
+        let ids: Vec<u64> = page.transactions.iter().map(|t| t.transaction_id).collect();
+        assert_eq!(ids, vec![6, 7, 8]);
+
+        // The earlier cursor now points into the archive, as does starting from the beginning
+        let stale = TransactionQuery {
+            cursor: first.next_cursor,
+            ..query(SortOrder::OldestFirst, 3)
+        };
+        assert!(page_reaches_archive(local, 5, &stale, None));
+        assert!(page_reaches_archive(local, 5, &query(SortOrder::OldestFirst, 3), Some(8)));
+
+        // The latest blocks are all local; a page running past the oldest of them isn't
+        let latest = query(SortOrder::NewestFirst, 3);
+        let page = run_query(local, 5, &indexes, &latest);
+        assert!(!page_reaches_archive(local, 5, &latest, page.next_cursor));
+        let beyond = query(SortOrder::NewestFirst, 10);
+        let page = run_query(local, 5, &indexes, &beyond);
+        assert!(page_reaches_archive(local, 5, &beyond, page.next_cursor));
+        let recent = TransactionQuery {
+            start_time: Some(600),
+            ..beyond
+        };
+        assert!(!page_reaches_archive(local, 5, &recent, None));
+
+        // Newest-first paging stops at the oldest local block
+        assert_eq!(pages(local, 5, query(SortOrder::NewestFirst, 3)), vec![vec![9, 8, 7], vec![6, 5]]);
+        let by_bob = TransactionQuery {
+            actor_id: Some("bob".to_string()),
+            ..query(SortOrder::NewestFirst, 10)
+        };
+        assert_eq!(run_query(local, 5, &indexes, &by_bob).transactions.len(), 3);
+    }
+}
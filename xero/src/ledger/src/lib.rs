@@ -1,88 +1,419 @@
-use ic_cdk::export::candid::{CandidType, Deserialize};
-use ic_cdk_macros::{init, query, update};
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use xero_types::{ErrorClass, Transaction, TransactionKind};
 
-/// Represents a transaction in the ledger.
+mod archive;
+mod export;
+mod http;
+mod idempotency;
+mod index;
+mod reports;
+
+use archive::{ArchiveInfo, ArchiveOptions, ArchiveState};
+use export::{ExportChunk, ExportFormat};
+use http::{HttpRequest, HttpResponse, StreamingCallbackHttpResponse, StreamingToken};
+use idempotency::IdempotencyKeys;
+use index::SecondaryIndexes;
+use reports::{ComplianceReport, ReportKind};
+
+/// Most blocks returned by a single `get_transactions` call.
+const MAX_BLOCKS_PER_RESPONSE: u64 = 1_000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum SortOrder {
+    OldestFirst,
+    NewestFirst,
+}
+
+/// Filters and paging for `list_transactions`; every filter is optional.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct TransactionQuery {
+    start_time: Option<u64>, // inclusive
+    end_time: Option<u64>, // inclusive
+    action_type: Option<String>,
+    actor_id: Option<String>,
+    item_id: Option<String>,
+    order: Option<SortOrder>, // defaults to OldestFirst
+    cursor: Option<u64>, // `next_cursor` from the previous page
+    limit: Option<u32>,
+}
+
+/// A page of transactions; `next_cursor` is `None` on the last page.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransactionPage {
+    transactions: Vec<Transaction>,
+    next_cursor: Option<u64>,
+}
+
+/// A span of blocks that has to be fetched from an archive's `get_transactions`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct ArchivedRange {
+    start: u64,
+    length: u64,
+    canister_id: Principal,
+}
+
+/// Blocks in the requested range that the ledger still holds, plus where to find the archived ones.
 #[derive(CandidType, Deserialize, Clone, Debug)]
-struct Transaction {
-    transaction_id: String,
-    timestamp: u64, // Unix timestamp
-    action_type: String, // e.g., "add_item", "update_item", "remove_item"
-    details: String, // JSON or structured details about the transaction
-    actor_id: String, // ID of the actor who initiated the transaction
+struct GetTransactionsResponse {
+    log_length: u64, // ID the next transaction will get
+    first_index: u64, // ID of the oldest block held by the ledger itself
+    transactions: Vec<Transaction>,
+    archived_transactions: Vec<ArchivedRange>,
+}
+
+/// Errors returned by the ledger canister API.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+enum LedgerError {
+    TransactionNotFound { transaction_id: u64 },
+    /// The transaction has been moved to an archive canister; fetch it from there.
+    TransactionArchived { transaction_id: u64, canister_id: Principal },
+    /// The idempotency key was already used for a different transaction.
+    IdempotencyKeyConflict { idempotency_key: String, transaction_id: u64 },
+    /// The query reaches transactions below `first_index`, which have moved to archive canisters.
+    /// Read those through `get_transactions` and the archives it points to, or start the query later.
+    RangeArchived { first_index: u64 },
+    Unauthorized { caller: Principal },
+}
+
+impl ErrorClass for LedgerError {
+    fn code(&self) -> &'static str {
+        match self {
+            LedgerError::TransactionNotFound { .. } => "transaction_not_found",
+            LedgerError::TransactionArchived { .. } => "transaction_archived",
+            LedgerError::IdempotencyKeyConflict { .. } => "idempotency_key_conflict",
+            LedgerError::RangeArchived { .. } => "range_archived",
+            LedgerError::Unauthorized { .. } => "unauthorized",
+        }
+    }
+
+    // Archived data never comes back to the ledger, so none of these clear up by waiting
+    fn is_retryable(&self) -> bool {
+        false
+    }
 }
 
 /// Type alias for a collection of transactions.
 type Ledger = Vec<Transaction>;
 
+/// What `pre_upgrade` saves; fields added later are optional so older saves still decode.
+type StableState = (Ledger, u64, HashMap<String, u64>, ArchiveState, Option<IdempotencyKeys>);
+
 // Thread-local storage for the ledger state.
 thread_local! {
-    static LEDGER: RefCell<Ledger> = RefCell::new(Vec::new());
-    static TRANSACTION_INDEX: RefCell<HashMap<String, usize>> = RefCell::new(HashMap::new());
+    static LEDGER: RefCell<Ledger> = const { RefCell::new(Vec::new()) };
+    static FIRST_LOCAL_INDEX: RefCell<u64> = const { RefCell::new(0) }; // ID of LEDGER[0]; earlier blocks are archived
+    static IDEMPOTENCY_KEYS: RefCell<IdempotencyKeys> = RefCell::new(IdempotencyKeys::default());
+    static SECONDARY_INDEXES: RefCell<SecondaryIndexes> = RefCell::new(SecondaryIndexes::default());
+    static ARCHIVE_STATE: RefCell<ArchiveState> = RefCell::new(ArchiveState::default());
+    static ARCHIVING: RefCell<bool> = const { RefCell::new(false) };
 }
 
 #[init]
-fn init() {
+fn init(options: Option<ArchiveOptions>) {
     LEDGER.with(|ledger| {
         *ledger.borrow_mut() = Vec::new();
     });
-    TRANSACTION_INDEX.with(|index| {
-        *index.borrow_mut() = HashMap::new();
+    IDEMPOTENCY_KEYS.with(|keys| {
+        *keys.borrow_mut() = IdempotencyKeys::default();
+    });
+    SECONDARY_INDEXES.with(|indexes| {
+        *indexes.borrow_mut() = SecondaryIndexes::default();
+    });
+    ARCHIVE_STATE.with(|state| {
+        state.borrow_mut().options = options.unwrap_or_default();
     });
 }
 
-/// Adds a transaction to the ledger.
-#[update]
-fn add_transaction(transaction_id: String, action_type: String, details: String, actor_id: String) -> Result<String, String> {
-    let timestamp = ic_cdk::api::time();
-    
-    // Ensure transaction_id is unique
-    if TRANSACTION_INDEX.with(|index| index.borrow().contains_key(&transaction_id)) {
-        return Err("Transaction ID already exists.".to_string());
+fn require_controller() -> Result<(), LedgerError> {
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err(LedgerError::Unauthorized { caller })
     }
+}
 
+/// Adds a free-form transaction to the ledger; kept for callers that predate `record_transaction`.
+#[update]
+fn add_transaction(
+    action_type: String,
+    details: String,
+    actor_id: String,
+    item_ids: Option<Vec<String>>,
+    idempotency_key: Option<String>,
+) -> Result<u64, LedgerError> {
     let transaction = Transaction {
-        transaction_id: transaction_id.clone(),
-        timestamp,
+        transaction_id: 0,
+        timestamp: ic_cdk::api::time(),
         action_type,
-        details,
+        details: details.clone(),
         actor_id,
+        item_ids: item_ids.unwrap_or_default(),
+        kind: TransactionKind::Legacy { details },
+        idempotency_key,
     };
+    append_transaction(transaction)
+}
 
-    // Add the transaction to the ledger and update index
-    LEDGER.with(|ledger| {
-        TRANSACTION_INDEX.with(|index| {
-            let mut ledger = ledger.borrow_mut();
-            let mut index = index.borrow_mut();
-            
-            index.insert(transaction_id.clone(), ledger.len());
-            ledger.push(transaction);
+/// Adds a transaction with a typed payload to the ledger and returns its ID.
+/// Retrying with the same idempotency key within 30 days returns the original ID instead of adding a duplicate,
+/// even once the original block has been archived.
+#[update]
+fn record_transaction(
+    kind: TransactionKind,
+    actor_id: String,
+    idempotency_key: Option<String>,
+) -> Result<u64, LedgerError> {
+    let transaction = Transaction {
+        transaction_id: 0,
+        timestamp: ic_cdk::api::time(),
+        action_type: kind.action_type().to_string(),
+        details: kind.summary(),
+        actor_id,
+        item_ids: kind.item_ids(),
+        kind,
+        idempotency_key,
+    };
+    append_transaction(transaction)
+}
+
+// Assigns the next ID, or returns the existing one for a retried idempotency key
+fn append_transaction(mut transaction: Transaction) -> Result<u64, LedgerError> {
+    if let Some(key) = &transaction.idempotency_key {
+        let used = IDEMPOTENCY_KEYS.with(|keys| {
+            let mut keys = keys.borrow_mut();
+            keys.prune(transaction.timestamp);
+            keys.get(key).cloned()
         });
+        if let Some(used) = used {
+            return if used.matches(&transaction.actor_id, &transaction.kind) {
+                Ok(used.transaction_id)
+            } else {
+                Err(LedgerError::IdempotencyKeyConflict {
+                    idempotency_key: key.clone(),
+                    transaction_id: used.transaction_id,
+                })
+            };
+        }
+    }
+
+    let transaction_id = LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        transaction.transaction_id = FIRST_LOCAL_INDEX.with(|index| *index.borrow()) + ledger.len() as u64;
+
+        IDEMPOTENCY_KEYS.with(|keys| keys.borrow_mut().insert(&transaction));
+        SECONDARY_INDEXES.with(|indexes| indexes.borrow_mut().insert(&transaction));
+
+        let transaction_id = transaction.transaction_id;
+        ledger.push(transaction);
+        transaction_id
     });
 
-    Ok(format!("Transaction '{}' successfully added.", transaction_id))
+    archive::schedule();
+    Ok(transaction_id)
 }
 
 /// Retrieves a specific transaction by ID.
 #[query]
-fn get_transaction(transaction_id: String) -> Result<Transaction, String> {
-    TRANSACTION_INDEX.with(|index| {
-        LEDGER.with(|ledger| {
-            let index = index.borrow();
-            let ledger = ledger.borrow();
-
-            index
-                .get(&transaction_id)
-                .and_then(|&pos| ledger.get(pos))
-                .cloned()
-                .ok_or_else(|| "Transaction not found.".to_string())
-        })
+fn get_transaction(transaction_id: u64) -> Result<Transaction, LedgerError> {
+    let first_index = FIRST_LOCAL_INDEX.with(|index| *index.borrow());
+    if transaction_id < first_index {
+        return Err(ARCHIVE_STATE.with(|state| match state.borrow().archive_for(transaction_id) {
+            Some(archive) => LedgerError::TransactionArchived {
+                transaction_id,
+                canister_id: archive.canister_id,
+            },
+            None => LedgerError::TransactionNotFound { transaction_id },
+        }));
+    }
+    LEDGER.with(|ledger| {
+        ledger
+            .borrow()
+            .get((transaction_id - first_index) as usize)
+            .cloned()
+            .ok_or(LedgerError::TransactionNotFound { transaction_id })
     })
 }
 
-/// Lists all transactions in the ledger.
+/// Lists one page of the transactions that match the query, oldest first unless asked otherwise.
+/// Fails with `RangeArchived` when the page needs archived blocks, which are only reachable through
+/// `get_transactions`, rather than silently leaving them out: an oldest-first query starting before the
+/// oldest local block, or a newest-first page running past it. The latest transactions always list.
 #[query]
-fn list_transactions() -> Vec<Transaction> {
-    LEDGER.with(|ledger| ledger.borrow().clone())
+fn list_transactions(query: TransactionQuery) -> Result<TransactionPage, LedgerError> {
+    let first_index = FIRST_LOCAL_INDEX.with(|index| *index.borrow());
+    LEDGER.with(|ledger| {
+        let ledger = ledger.borrow();
+        let page =
+            SECONDARY_INDEXES.with(|indexes| index::run_query(&ledger, first_index, &indexes.borrow(), &query));
+        if index::page_reaches_archive(&ledger, first_index, &query, page.next_cursor) {
+            return Err(LedgerError::RangeArchived { first_index });
+        }
+        Ok(page)
+    })
+}
+
+/// Returns the blocks with IDs in `start..start + length` that the ledger still holds,
+/// and the archive ranges covering any part of the request that has been archived.
+#[query]
+fn get_transactions(start: u64, length: u64) -> GetTransactionsResponse {
+    let first_index = FIRST_LOCAL_INDEX.with(|index| *index.borrow());
+    let end = start.saturating_add(length.min(MAX_BLOCKS_PER_RESPONSE));
+
+    let archived_transactions = ARCHIVE_STATE.with(|state| {
+        state
+            .borrow()
+            .archives
+            .iter()
+            .filter_map(|archive| {
+                let from = start.max(archive.block_range_start);
+                let to = end.min(archive.block_range_end);
+                (from < to).then(|| ArchivedRange {
+                    start: from,
+                    length: to - from,
+                    canister_id: archive.canister_id,
+                })
+            })
+            .collect()
+    });
+
+    LEDGER.with(|ledger| {
+        let ledger = ledger.borrow();
+        let log_length = first_index + ledger.len() as u64;
+        let from = start.max(first_index).min(log_length);
+        let to = end.max(from).min(log_length);
+        GetTransactionsResponse {
+            log_length,
+            first_index,
+            transactions: ledger[(from - first_index) as usize..(to - first_index) as usize].to_vec(),
+            archived_transactions,
+        }
+    })
+}
+
+/// Exports one chunk of the transactions that match the query, as CSV or JSON Lines.
+/// Keep passing `next_cursor` back as `query.cursor` until it comes back empty.
+/// Fails with `RangeArchived` like `list_transactions` when the query reaches archived blocks.
+#[query]
+fn export_transactions(query: TransactionQuery, format: ExportFormat) -> Result<ExportChunk, LedgerError> {
+    export::export_chunk(&query, format)
+}
+
+/// Serves ledger exports over HTTP, e.g. `/export.csv?from=..&to=..`.
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    http::handle(&request)
+}
+
+#[query]
+fn http_request_streaming_callback(token: StreamingToken) -> StreamingCallbackHttpResponse {
+    http::next_chunk(token)
+}
+
+/// Builds the compliance report for transactions with timestamps in `start_time..=end_time`.
+/// Fails with `RangeArchived` when the period reaches archived blocks.
+#[query]
+fn get_compliance_report(start_time: Option<u64>, end_time: Option<u64>) -> Result<ComplianceReport, LedgerError> {
+    reports::compliance_report(start_time, end_time)
+}
+
+/// Renders one section of the compliance report as CSV.
+#[query]
+fn export_compliance_report(
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    kind: ReportKind,
+) -> Result<String, LedgerError> {
+    reports::compliance_report(start_time, end_time).map(|report| reports::to_csv(&report, kind))
+}
+
+/// Lists the archive canisters spawned by the ledger and the block ranges they hold.
+#[query]
+fn get_archives() -> Vec<ArchiveInfo> {
+    ARCHIVE_STATE.with(|state| state.borrow().archives.clone())
+}
+
+/// Uploads the `ledger_archive` wasm module installed into newly spawned archives.
+#[update]
+fn set_archive_wasm(wasm_module: Vec<u8>) -> Result<String, LedgerError> {
+    require_controller()?;
+    let size = wasm_module.len();
+    ARCHIVE_STATE.with(|state| state.borrow_mut().wasm = Some(wasm_module));
+    archive::schedule();
+    Ok(format!("Archive wasm set ({} bytes)", size))
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    LEDGER.with(|ledger| {
+        FIRST_LOCAL_INDEX.with(|first_index| {
+            IDEMPOTENCY_KEYS.with(|keys| {
+                ARCHIVE_STATE.with(|state| {
+                    // The third slot held the key→ID map before keys outlived their blocks; it stays so the
+                    // layout keeps decoding, and the keys follow the archive state
+                    ic_cdk::storage::stable_save((
+                        &*ledger.borrow(),
+                        *first_index.borrow(),
+                        HashMap::<String, u64>::new(),
+                        &*state.borrow(),
+                        Some(&*keys.borrow()),
+                    ))
+                    .unwrap();
+                });
+            });
+        });
+    });
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    // The first release had no upgrade hooks, so upgrading from it finds stable memory empty; its
+    // transactions lived only on the heap and the ledger starts from the defaults `init` would set
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        ic_cdk::println!("No saved ledger state; starting from an empty ledger");
+        return;
+    }
+    // Anything else that doesn't decode fails the upgrade rather than dropping the history
+    let (ledger, first_index, _, state, keys): StableState = ic_cdk::storage::stable_restore()
+        .unwrap_or_else(|error| ic_cdk::trap(&format!("Cannot restore ledger state: {}", error)));
+    // Saved before keys were kept apart from the blocks; only the keys of local blocks were left then
+    let keys = keys.unwrap_or_else(|| IdempotencyKeys::from_blocks(&ledger, ic_cdk::api::time()));
+
+    SECONDARY_INDEXES.with(|indexes| *indexes.borrow_mut() = SecondaryIndexes::rebuild(&ledger));
+    LEDGER.with(|stored| *stored.borrow_mut() = ledger);
+    FIRST_LOCAL_INDEX.with(|index| *index.borrow_mut() = first_index);
+    IDEMPOTENCY_KEYS.with(|stored| *stored.borrow_mut() = keys);
+    ARCHIVE_STATE.with(|stored| *stored.borrow_mut() = state);
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use candid::utils::{service_equal, CandidSource};
+    use std::path::Path;
+
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let did_file = Path::new(env!("CARGO_MANIFEST_DIR")).join("ledger.did");
+        service_equal(CandidSource::Text(&super::__export_service()), CandidSource::File(&did_file))
+            .expect("ledger.did is out of date with the Rust interface; run ./generate_did.sh");
+    }
+
+    #[test]
+    fn state_saved_without_idempotency_keys_still_decodes() {
+        let saved = candid::encode_args((
+            super::Ledger::new(),
+            7u64,
+            std::collections::HashMap::<String, u64>::new(),
+            super::ArchiveState::default(),
+        ))
+        .unwrap();
+        let (_, first_index, _, _, keys): super::StableState = candid::decode_args(&saved).unwrap();
+        assert_eq!(first_index, 7);
+        assert!(keys.is_none());
+    }
 }
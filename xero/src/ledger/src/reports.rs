@@ -0,0 +1,242 @@
+use candid::{CandidType, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use xero_types::units::MILLI_PER_UNIT;
+use xero_types::{Money, Quantity, Transaction, TransactionKind};
+
+use crate::export::csv_field;
+use crate::{index, LedgerError, FIRST_LOCAL_INDEX, LEDGER};
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PriceChangeFrequency {
+    pub item_id: String,
+    pub change_count: u32, // individual and bulk price changes
+}
+
+/// The deepest single markdown recorded for an item in the period.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MarkdownDepth {
+    pub item_id: String,
+    pub max_markdown_bps: u32, // reduction in basis points of the old price
+    pub old_value: Money,
+    pub new_value: Money,
+    pub transaction_id: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WasteByReason {
+    pub reason: String,
+    pub total: Quantity, // one entry per reason and unit, since units can't be added across dimensions
+    pub disposal_count: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ActorPriceChanges {
+    pub actor_id: String,
+    pub item_ids: Vec<String>,
+    pub change_count: u32,
+}
+
+/// Aggregates over the ledger transactions with timestamps in `start_time..=end_time`.
+/// Periods reaching archived blocks are rejected, so the report always covers the whole period;
+/// `first_transaction_id` is the oldest block the ledger held when it was built.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ComplianceReport {
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub first_transaction_id: u64,
+    pub transactions_scanned: u64,
+    pub price_change_frequency: Vec<PriceChangeFrequency>,
+    pub markdown_depth: Vec<MarkdownDepth>,
+    pub waste_by_reason: Vec<WasteByReason>,
+    pub price_changes_by_actor: Vec<ActorPriceChanges>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ReportKind {
+    PriceChangeFrequency,
+    MarkdownDepth,
+    WasteByReason,
+    PriceChangesByActor,
+}
+
+// Fails with `RangeArchived` when the period starts before the locally held blocks, rather than covering part of it
+pub fn compliance_report(start_time: Option<u64>, end_time: Option<u64>) -> Result<ComplianceReport, LedgerError> {
+    let first_index = FIRST_LOCAL_INDEX.with(|index| *index.borrow());
+    LEDGER.with(|ledger| {
+        let ledger = ledger.borrow();
+        if index::period_reaches_archive(&ledger, first_index, start_time) {
+            return Err(LedgerError::RangeArchived { first_index });
+        }
+        // Timestamps only grow, so the period is a contiguous span of the ledger
+        let lo = start_time.map_or(0, |start| ledger.partition_point(|t| t.timestamp < start));
+        let hi = end_time.map_or(ledger.len(), |end| ledger.partition_point(|t| t.timestamp <= end));
+        let period = &ledger[lo..hi.max(lo)];
+
+        Ok(ComplianceReport {
+            start_time,
+            end_time,
+            first_transaction_id: first_index,
+            transactions_scanned: period.len() as u64,
+            price_change_frequency: price_change_frequency(period),
+            markdown_depth: markdown_depth(period),
+            waste_by_reason: waste_by_reason(period),
+            price_changes_by_actor: price_changes_by_actor(period),
+        })
+    })
+}
+
+fn price_change_frequency(period: &[Transaction]) -> Vec<PriceChangeFrequency> {
+    let mut counts: BTreeMap<&str, u32> = BTreeMap::new();
+    for transaction in period {
+        for item_id in changed_prices(&transaction.kind) {
+            *counts.entry(item_id).or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(item_id, change_count)| PriceChangeFrequency { item_id: item_id.to_string(), change_count })
+        .collect()
+}
+
+fn markdown_depth(period: &[Transaction]) -> Vec<MarkdownDepth> {
+    let mut deepest: BTreeMap<&str, MarkdownDepth> = BTreeMap::new();
+    for transaction in period {
+        if let TransactionKind::PriceChange { item_id, old_value, new_value, .. } = &transaction.kind {
+            let Some(bps) = markdown_bps(old_value, new_value) else { continue };
+            if deepest.get(item_id.as_str()).is_none_or(|d| bps > d.max_markdown_bps) {
+                deepest.insert(
+                    item_id,
+                    MarkdownDepth {
+                        item_id: item_id.clone(),
+                        max_markdown_bps: bps,
+                        old_value: old_value.clone(),
+                        new_value: new_value.clone(),
+                        transaction_id: transaction.transaction_id,
+                    },
+                );
+            }
+        }
+    }
+    deepest.into_values().collect()
+}
+
+// Reduction in basis points; `None` for increases and prices that can't be compared
+fn markdown_bps(old_value: &Money, new_value: &Money) -> Option<u32> {
+    if old_value.currency != new_value.currency || new_value.minor_units >= old_value.minor_units {
+        return None;
+    }
+    let reduction = u128::from(old_value.minor_units - new_value.minor_units);
+    Some((reduction * 10_000 / u128::from(old_value.minor_units)) as u32)
+}
+
+fn waste_by_reason(period: &[Transaction]) -> Vec<WasteByReason> {
+    let mut totals: BTreeMap<(&str, &str), WasteByReason> = BTreeMap::new();
+    for transaction in period {
+        if let TransactionKind::WasteDisposal { quantity, reason, .. } = &transaction.kind {
+            let entry = totals.entry((reason.as_str(), quantity.unit.symbol())).or_insert_with(|| WasteByReason {
+                reason: reason.clone(),
+                total: Quantity { milli_units: 0, unit: quantity.unit },
+                disposal_count: 0,
+            });
+            entry.total.milli_units = entry.total.milli_units.saturating_add(quantity.milli_units);
+            entry.disposal_count += 1;
+        }
+    }
+    totals.into_values().collect()
+}
+
+fn price_changes_by_actor(period: &[Transaction]) -> Vec<ActorPriceChanges> {
+    let mut actors: BTreeMap<&str, (BTreeSet<&str>, u32)> = BTreeMap::new();
+    for transaction in period {
+        let items = changed_prices(&transaction.kind);
+        if items.is_empty() {
+            continue;
+        }
+        let (item_ids, change_count) = actors.entry(&transaction.actor_id).or_default();
+        item_ids.extend(items);
+        *change_count += 1;
+    }
+    actors
+        .into_iter()
+        .map(|(actor_id, (item_ids, change_count))| ActorPriceChanges {
+            actor_id: actor_id.to_string(),
+            item_ids: item_ids.into_iter().map(str::to_string).collect(),
+            change_count,
+        })
+        .collect()
+}
+
+// Items whose price the transaction changed
+fn changed_prices(kind: &TransactionKind) -> Vec<&str> {
+    match kind {
+        TransactionKind::PriceChange { item_id, .. } => vec![item_id.as_str()],
+        TransactionKind::BulkPriceChange { item_ids, .. } => item_ids.iter().map(String::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Renders one section of the report as CSV: a summary of the period covered, a blank line,
+/// then the section with its own header row.
+pub fn to_csv(report: &ComplianceReport, kind: ReportKind) -> String {
+    let mut out = String::new();
+    out.push_str("start_time,end_time,first_transaction_id,transactions_scanned\n");
+    let _ = writeln!(
+        out,
+        "{},{},{},{}\n",
+        report.start_time.map_or(String::new(), |t| t.to_string()),
+        report.end_time.map_or(String::new(), |t| t.to_string()),
+        report.first_transaction_id,
+        report.transactions_scanned
+    );
+    match kind {
+        ReportKind::PriceChangeFrequency => {
+            out.push_str("item_id,change_count\n");
+            for row in &report.price_change_frequency {
+                let _ = writeln!(out, "{},{}", csv_field(&row.item_id), row.change_count);
+            }
+        }
+        ReportKind::MarkdownDepth => {
+            out.push_str("item_id,max_markdown_percent,old_value,new_value,transaction_id\n");
+            for row in &report.markdown_depth {
+                let _ = writeln!(
+                    out,
+                    "{},{}.{:02},{},{},{}",
+                    csv_field(&row.item_id),
+                    row.max_markdown_bps / 100,
+                    row.max_markdown_bps % 100,
+                    csv_field(&row.old_value.to_string()),
+                    csv_field(&row.new_value.to_string()),
+                    row.transaction_id
+                );
+            }
+        }
+        ReportKind::WasteByReason => {
+            out.push_str("reason,quantity,unit,disposal_count\n");
+            for row in &report.waste_by_reason {
+                let _ = writeln!(
+                    out,
+                    "{},{}.{:03},{},{}",
+                    csv_field(&row.reason),
+                    row.total.whole_units(),
+                    row.total.milli_units % MILLI_PER_UNIT,
+                    row.total.unit.symbol(),
+                    row.disposal_count
+                );
+            }
+        }
+        ReportKind::PriceChangesByActor => {
+            out.push_str("actor_id,change_count,item_ids\n");
+            for row in &report.price_changes_by_actor {
+                let _ = writeln!(
+                    out,
+                    "{},{},{}",
+                    csv_field(&row.actor_id),
+                    row.change_count,
+                    csv_field(&row.item_ids.join(";"))
+                );
+            }
+        }
+    }
+    out
+}
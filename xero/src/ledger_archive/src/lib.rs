@@ -0,0 +1,133 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use std::cell::RefCell;
+use xero_types::{ErrorClass, Transaction};
+
+/// Errors returned by the ledger archive canister API.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+enum ArchiveError {
+    /// Only the ledger that spawned this archive may append to it.
+    Unauthorized { caller: Principal },
+    /// Blocks must be appended without gaps; `expected` is the next ID this archive accepts.
+    NonContiguous { expected: u64, received: u64 },
+}
+
+impl ErrorClass for ArchiveError {
+    fn code(&self) -> &'static str {
+        match self {
+            ArchiveError::Unauthorized { .. } => "unauthorized",
+            ArchiveError::NonContiguous { .. } => "non_contiguous",
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        false
+    }
+}
+
+// Thread-local storage for the archive state.
+thread_local! {
+    static LEDGER_ID: RefCell<Principal> = const { RefCell::new(Principal::anonymous()) };
+    static FIRST_INDEX: RefCell<u64> = const { RefCell::new(0) };
+    static BLOCKS: RefCell<Vec<Transaction>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Called by the ledger when it spawns the archive; `first_index` is the ID of the first block it will receive.
+#[init]
+fn init(ledger_id: Principal, first_index: u64) {
+    LEDGER_ID.with(|id| *id.borrow_mut() = ledger_id);
+    FIRST_INDEX.with(|index| *index.borrow_mut() = first_index);
+}
+
+/// Appends blocks moved out of the ledger and returns the ID the next block must have.
+/// Blocks the archive already holds are skipped, so the ledger can safely retry a chunk.
+#[update]
+fn append_transactions(transactions: Vec<Transaction>) -> Result<u64, ArchiveError> {
+    let caller = ic_cdk::caller();
+    if LEDGER_ID.with(|id| *id.borrow() != caller) {
+        return Err(ArchiveError::Unauthorized { caller });
+    }
+
+    let first_index = FIRST_INDEX.with(|index| *index.borrow());
+    BLOCKS.with(|blocks| {
+        let mut blocks = blocks.borrow_mut();
+        for transaction in transactions {
+            let expected = first_index + blocks.len() as u64;
+            if transaction.transaction_id < expected {
+                continue;
+            }
+            if transaction.transaction_id > expected {
+                return Err(ArchiveError::NonContiguous {
+                    expected,
+                    received: transaction.transaction_id,
+                });
+            }
+            blocks.push(transaction);
+        }
+        Ok(first_index + blocks.len() as u64)
+    })
+}
+
+/// Returns the archived blocks with IDs in `start..start + length`.
+#[query]
+fn get_transactions(start: u64, length: u64) -> Vec<Transaction> {
+    let first_index = FIRST_INDEX.with(|index| *index.borrow());
+    BLOCKS.with(|blocks| {
+        let blocks = blocks.borrow();
+        let from = start.saturating_sub(first_index).min(blocks.len() as u64) as usize;
+        let to = start
+            .saturating_add(length)
+            .saturating_sub(first_index)
+            .min(blocks.len() as u64) as usize;
+        blocks[from..to].to_vec()
+    })
+}
+
+/// Returns the half-open range of block IDs held by this archive.
+#[query]
+fn get_block_range() -> (u64, u64) {
+    let first_index = FIRST_INDEX.with(|index| *index.borrow());
+    let len = BLOCKS.with(|blocks| blocks.borrow().len() as u64);
+    (first_index, first_index + len)
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    LEDGER_ID.with(|ledger_id| {
+        FIRST_INDEX.with(|first_index| {
+            BLOCKS.with(|blocks| {
+                ic_cdk::storage::stable_save((
+                    *ledger_id.borrow(),
+                    *first_index.borrow(),
+                    &*blocks.borrow(),
+                ))
+                .unwrap();
+            });
+        });
+    });
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let (ledger_id, first_index, blocks): (Principal, u64, Vec<Transaction>) =
+        ic_cdk::storage::stable_restore().unwrap();
+
+    LEDGER_ID.with(|id| *id.borrow_mut() = ledger_id);
+    FIRST_INDEX.with(|index| *index.borrow_mut() = first_index);
+    BLOCKS.with(|stored| *stored.borrow_mut() = blocks);
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use candid::utils::{service_equal, CandidSource};
+    use std::path::Path;
+
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let did_file = Path::new(env!("CARGO_MANIFEST_DIR")).join("ledger_archive.did");
+        service_equal(CandidSource::Text(&super::__export_service()), CandidSource::File(&did_file))
+            .expect("ledger_archive.did is out of date with the Rust interface; run ./generate_did.sh");
+    }
+}
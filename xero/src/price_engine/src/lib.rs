@@ -1,12 +1,16 @@
-use ic_cdk::export::candid::{CandidType, Deserialize};
-use ic_cdk::export::Principal;
-use ic_cdk_macros::{init, query, update};
+use candid::types::value::IDLValue;
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
 use serde::Serialize;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use xero_types::inventory::{InventoryError, ItemDetails};
+use xero_types::ledger::PriceRuleState;
+use xero_types::money::DEFAULT_CURRENCY;
+use xero_types::{CallFailure, Dependencies, ErrorClass, Money, RoundingMode, TransactionKind, UnitOfMeasure};
 
 /// Pricing rule struct to define and track each rule.
-#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
 struct PricingRule {
     rule_name: String,
     rule_description: String,
@@ -17,52 +21,134 @@ struct PricingRule {
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 struct PriceAdjustmentResult {
     item_id: String,
-    new_price: f32,
+    new_price: Money,
+    unit: UnitOfMeasure, // new_price is per one unit, e.g. per kg for weighted items
+}
+
+/// Errors returned by the price engine canister API.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+enum PriceEngineError {
+    InvalidInput { field: String, reason: String },
+    Unauthorized { caller: Principal },
+    /// The inventory canister answered the call with an error.
+    InventoryRejected { error: InventoryError },
+    /// The ledger answered `record_transaction` with an error, shown as text.
+    LedgerRejected { reason: String },
+    /// The sibling canister has not been configured through the init argument or `set_dependencies`.
+    DependencyNotConfigured { dependency: String },
+    /// A call to a sibling canister was rejected.
+    CallFailed(CallFailure),
 }
 
+impl From<CallFailure> for PriceEngineError {
+    fn from(failure: CallFailure) -> Self {
+        PriceEngineError::CallFailed(failure)
+    }
+}
+
+impl ErrorClass for PriceEngineError {
+    fn code(&self) -> &'static str {
+        match self {
+            PriceEngineError::InvalidInput { .. } => "invalid_input",
+            PriceEngineError::Unauthorized { .. } => "unauthorized",
+            PriceEngineError::InventoryRejected { .. } => "inventory_rejected",
+            PriceEngineError::LedgerRejected { .. } => "ledger_rejected",
+            PriceEngineError::DependencyNotConfigured { .. } => "dependency_not_configured",
+            PriceEngineError::CallFailed(_) => "call_failed",
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            PriceEngineError::InventoryRejected { error } => error.is_retryable(),
+            PriceEngineError::CallFailed(failure) => failure.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+impl PriceEngineError {
+    fn dependency_not_configured(dependency: &str) -> Self {
+        PriceEngineError::DependencyNotConfigured {
+            dependency: dependency.to_string(),
+        }
+    }
+}
 
 thread_local! {
     static PRICING_RULES: RefCell<HashMap<String, PricingRule>> = RefCell::new(HashMap::new());
+    static ROUNDING_MODE: RefCell<RoundingMode> = const { RefCell::new(RoundingMode::HalfUp) };
+    static DEPENDENCIES: RefCell<Dependencies> = RefCell::new(Dependencies::default());
+}
+
+fn default_pricing_rules() -> HashMap<String, PricingRule> {
+    let mut rules = HashMap::new();
+    rules.insert(
+        "near_expiration".to_string(),
+        PricingRule {
+            rule_name: "near_expiration".to_string(),
+            rule_description: "Reduce price by 30% if item is within 3 days of expiration.".to_string(),
+            active: true,
+        },
+    );
+    rules.insert(
+        "low_stock_high_demand".to_string(),
+        PricingRule {
+            rule_name: "low_stock_high_demand".to_string(),
+            rule_description: "Increase price by 10% if demand is high and stock is low.".to_string(),
+            active: true,
+        },
+    );
+    rules
 }
 
-/// Initialize default pricing rules.
+/// Initialize default pricing rules and the sibling canister IDs.
 #[init]
-fn init() {
+fn init(dependencies: Option<Dependencies>) {
     PRICING_RULES.with(|rules| {
-        let mut rules = rules.borrow_mut();
-        rules.insert(
-            "near_expiration".to_string(),
-            PricingRule {
-                rule_name: "near_expiration".to_string(),
-                rule_description: "Reduce price by 30% if item is within 3 days of expiration.".to_string(),
-                active: true,
-            },
-        );
-        rules.insert(
-            "low_stock_high_demand".to_string(),
-            PricingRule {
-                rule_name: "low_stock_high_demand".to_string(),
-                rule_description: "Increase price by 10% if demand is high and stock is low.".to_string(),
-                active: true,
-            },
-        );
+        *rules.borrow_mut() = default_pricing_rules();
     });
+    DEPENDENCIES.with(|deps| {
+        *deps.borrow_mut() = dependencies.unwrap_or_default();
+    });
+}
+
+fn require_controller() -> Result<(), PriceEngineError> {
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err(PriceEngineError::Unauthorized { caller })
+    }
+}
+
+fn inventory_canister_id() -> Result<Principal, PriceEngineError> {
+    DEPENDENCIES
+        .with(|deps| deps.borrow().inventory)
+        .ok_or_else(|| PriceEngineError::dependency_not_configured("inventory"))
+}
+
+fn ledger_canister_id() -> Result<Principal, PriceEngineError> {
+    DEPENDENCIES
+        .with(|deps| deps.borrow().ledger)
+        .ok_or_else(|| PriceEngineError::dependency_not_configured("ledger"))
 }
 
 /// Adjust the price of an item based on active pricing rules.
 #[update]
-async fn adjust_price(item_id: String) -> Result<PriceAdjustmentResult, String> {
-    // Convert inventory canister ID to Principal
-    let inventory_canister_id = Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap();
-    let (base_price, expiration_date): (f32, u64) = ic_cdk::api::call::call(
-        inventory_canister_id,
-        "get_item_details",
-        (item_id.clone(),),
-    )
-    .await
-    .map_err(|e| format!("Failed to fetch item details: {:?}", e))?;
+async fn adjust_price(item_id: String) -> Result<PriceAdjustmentResult, PriceEngineError> {
+    let inventory = inventory_canister_id()?;
+    let (details,): (Result<ItemDetails, InventoryError>,) =
+        ic_cdk::api::call::call(inventory, "get_item_details", (item_id.clone(),))
+            .await
+            .map_err(|e| CallFailure::new(inventory, "get_item_details", e))?;
+    let ItemDetails { price: base_price, expiration_date, unit, .. } =
+        details.map_err(|error| PriceEngineError::InventoryRejected { error })?;
 
-    let mut new_price = base_price;
+    // Rule multipliers are accumulated as a ratio so the price is rounded only once.
+    let mut numerator: u64 = 1;
+    let mut denominator: u64 = 1;
+    let mut applied_rules = Vec::new();
 
     // Apply the pricing rules.
     PRICING_RULES.with(|rules| {
@@ -70,33 +156,61 @@ async fn adjust_price(item_id: String) -> Result<PriceAdjustmentResult, String>
 
         if let Some(rule) = rules.get("near_expiration") {
             if rule.active && expiration_date <= ic_cdk::api::time() + (3 * 24 * 60 * 60 * 1_000_000_000) {
-                new_price *= 0.7; // Reduce price by 30%
+                // Reduce price by 30%
+                numerator *= 7;
+                denominator *= 10;
+                applied_rules.push(rule.rule_name.clone());
             }
         }
 
         if let Some(rule) = rules.get("low_stock_high_demand") {
             if rule.active && check_low_stock_high_demand(item_id.clone()) {
-                new_price *= 1.1; // Increase price by 10%
+                // Increase price by 10%
+                numerator *= 11;
+                denominator *= 10;
+                applied_rules.push(rule.rule_name.clone());
             }
         }
     });
 
-    // Convert ledger canister ID to Principal
-    let ledger_canister_id = Principal::from_text("be2us-64aaa-aaaaa-qaabq-cai").unwrap();
-    let _log_result: () = ic_cdk::api::call::call(
-        ledger_canister_id,
-        "add_transaction",
-        (
-            format!("price_adjustment_{}", item_id),
-            "adjust_price".to_string(),
-            format!("Adjusted price to {}", new_price),
-            "price_engine".to_string(),
-        ),
+    let rounding_mode = ROUNDING_MODE.with(|mode| *mode.borrow());
+    let new_price = base_price
+        .scale(numerator, denominator, rounding_mode)
+        .ok_or_else(|| PriceEngineError::InvalidInput {
+            field: "rounding_mode".to_string(),
+            reason: format!("Rounding mode {:?} is not valid for {}.", rounding_mode, base_price.currency),
+        })?;
+
+    let reason = if applied_rules.is_empty() {
+        "no rule applied".to_string()
+    } else {
+        applied_rules.join(", ")
+    };
+    log_to_ledger(TransactionKind::PriceChange {
+        item_id: item_id.clone(),
+        old_value: base_price,
+        new_value: new_price.clone(),
+        reason,
+    })
+    .await?;
+
+    Ok(PriceAdjustmentResult { item_id, new_price, unit })
+}
+
+/// Records a typed transaction in the ledger canister, which assigns its ID.
+async fn log_to_ledger(kind: TransactionKind) -> Result<(), PriceEngineError> {
+    let ledger = ledger_canister_id()?;
+    // LedgerError is specific to the ledger canister; it is only shown, never matched on
+    let (result,): (Result<u64, IDLValue>,) = ic_cdk::api::call::call(
+        ledger,
+        "record_transaction",
+        (kind, "price_engine".to_string(), None::<String>),
     )
     .await
-    .map_err(|e| format!("Failed to log price adjustment: {:?}", e))?;
-
-    Ok(PriceAdjustmentResult { item_id, new_price })
+    .map_err(|e| CallFailure::new(ledger, "record_transaction", e))?;
+    result.map(|_| ()).map_err(|error| PriceEngineError::LedgerRejected {
+        reason: error.to_string(),
+    })
 }
 
 /// Helper function to check if an item has low stock and high demand.
@@ -111,19 +225,135 @@ fn get_pricing_rules() -> Vec<PricingRule> {
     PRICING_RULES.with(|rules| rules.borrow().values().cloned().collect())
 }
 
-/// Set a custom pricing rule.
+/// Set a custom pricing rule and record the change in the ledger; restricted to controllers.
 #[update]
-fn set_pricing_rule(rule_name: String, rule_description: String, active: bool) -> Result<String, String> {
+async fn set_pricing_rule(rule_name: String, rule_description: String, active: bool) -> Result<String, PriceEngineError> {
+    require_controller()?;
+    if rule_name.trim().is_empty() {
+        return Err(PriceEngineError::InvalidInput {
+            field: "rule_name".to_string(),
+            reason: "Rule name cannot be empty.".to_string(),
+        });
+    }
+
+    let new_rule = PriceRuleState {
+        description: rule_description.clone(),
+        active,
+    };
+    let written = PricingRule {
+        rule_name: rule_name.clone(),
+        rule_description,
+        active,
+    };
+    let old_rule = PRICING_RULES.with(|rules| rules.borrow_mut().insert(rule_name.clone(), written.clone()));
+
+    let logged = log_to_ledger(TransactionKind::PriceRuleUpdate {
+        rule_name: rule_name.clone(),
+        old_rule: old_rule.clone().map(|rule| PriceRuleState {
+            description: rule.rule_description,
+            active: rule.active,
+        }),
+        new_rule,
+    })
+    .await;
+    // A change the ledger didn't record is undone, so the audit trail covers every rule in effect. Another
+    // call may have set the rule while this one awaited the ledger; its value is left alone.
+    if logged.is_err() {
+        PRICING_RULES.with(|rules| {
+            let mut rules = rules.borrow_mut();
+            if rules.get(&rule_name) != Some(&written) {
+                return;
+            }
+            match old_rule {
+                Some(rule) => rules.insert(rule_name, rule),
+                None => rules.remove(&rule_name),
+            };
+        });
+    }
+    logged?;
+
+    Ok("Pricing rule updated successfully.".to_string())
+}
+
+/// Retrieve the rounding mode applied to adjusted prices.
+#[query]
+fn get_rounding_mode() -> RoundingMode {
+    ROUNDING_MODE.with(|mode| *mode.borrow())
+}
+
+/// Set the rounding mode applied to adjusted prices, e.g. `PriceEnding(99)` for x.99 prices.
+/// Restricted to controllers.
+/// Endings must fit the minor units of the default currency. Prices in currencies with fewer minor units
+/// still fail to adjust under a larger ending.
+#[update]
+fn set_rounding_mode(rounding_mode: RoundingMode) -> Result<String, PriceEngineError> {
+    require_controller()?;
+    let minor_per_major = Money::new(0, DEFAULT_CURRENCY).minor_per_major();
+    if let RoundingMode::PriceEnding(ending) = rounding_mode {
+        if ending >= minor_per_major {
+            return Err(PriceEngineError::InvalidInput {
+                field: "rounding_mode".to_string(),
+                reason: format!("Price endings must be below {} minor units.", minor_per_major),
+            });
+        }
+    }
+    ROUNDING_MODE.with(|mode| *mode.borrow_mut() = rounding_mode);
+    Ok(format!("Rounding mode set to {:?}.", rounding_mode))
+}
+
+/// Retrieve the IDs of the inventory and ledger canisters this canister calls.
+#[query]
+fn get_dependencies() -> Dependencies {
+    DEPENDENCIES.with(|deps| deps.borrow().clone())
+}
+
+/// Replace the sibling canister IDs; restricted to controllers.
+#[update]
+fn set_dependencies(dependencies: Dependencies) -> Result<String, PriceEngineError> {
+    require_controller()?;
+    DEPENDENCIES.with(|deps| *deps.borrow_mut() = dependencies);
+    Ok("Dependencies updated.".to_string())
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
     PRICING_RULES.with(|rules| {
-        let mut rules = rules.borrow_mut();
-        rules.insert(
-            rule_name.clone(),
-            PricingRule {
-                rule_name,
-                rule_description,
-                active,
-            },
-        );
+        ROUNDING_MODE.with(|mode| {
+            DEPENDENCIES.with(|deps| {
+                ic_cdk::storage::stable_save((&*rules.borrow(), *mode.borrow(), &*deps.borrow())).unwrap();
+            });
+        });
     });
-    Ok("Pricing rule updated successfully.".to_string())
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    // Nothing was saved before the upgrade hooks existed, so stable memory is empty; start from the defaults
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        PRICING_RULES.with(|stored| *stored.borrow_mut() = default_pricing_rules());
+        return;
+    }
+    // Anything else that doesn't decode fails the upgrade rather than resetting the rules and dependencies
+    let (rules, rounding_mode, dependencies): (HashMap<String, PricingRule>, RoundingMode, Dependencies) =
+        ic_cdk::storage::stable_restore()
+            .unwrap_or_else(|error| ic_cdk::trap(&format!("Cannot restore price engine state: {}", error)));
+
+    PRICING_RULES.with(|stored| *stored.borrow_mut() = rules);
+    ROUNDING_MODE.with(|mode| *mode.borrow_mut() = rounding_mode);
+    DEPENDENCIES.with(|deps| *deps.borrow_mut() = dependencies);
+}
+
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use candid::utils::{service_equal, CandidSource};
+    use std::path::Path;
+
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let did_file = Path::new(env!("CARGO_MANIFEST_DIR")).join("price_engine.did");
+        service_equal(CandidSource::Text(&super::__export_service()), CandidSource::File(&did_file))
+            .expect("price_engine.did is out of date with the Rust interface; run ./generate_did.sh");
+    }
 }
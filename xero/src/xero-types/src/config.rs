@@ -0,0 +1,12 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+/// IDs of the sibling canisters a canister calls.
+/// Passed as an init argument and replaced by controllers through `set_dependencies`.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dependencies {
+    pub inventory: Option<Principal>,
+    pub ledger: Option<Principal>,
+    pub price_engine: Option<Principal>,
+    pub data_aggregator: Option<Principal>,
+}
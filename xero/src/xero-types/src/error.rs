@@ -0,0 +1,87 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::RejectionCode;
+use serde::Serialize;
+use std::fmt;
+
+/// Stable classification shared by the error types of every Xero canister.
+pub trait ErrorClass {
+    /// Snake-case name of the error, stable across releases, for logs and client-side matching.
+    fn code(&self) -> &'static str;
+
+    /// Whether repeating the same request later may succeed without changing it.
+    fn is_retryable(&self) -> bool;
+}
+
+/// A call to a sibling canister that was rejected; the reject code is passed through as-is.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct CallFailure {
+    pub canister: Principal,
+    pub method: String,
+    pub reject_code: u32,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl CallFailure {
+    /// Wraps the error of an `ic_cdk::api::call` to `method` on `canister`.
+    pub fn new(canister: Principal, method: &str, (code, message): (RejectionCode, String)) -> Self {
+        CallFailure {
+            canister,
+            method: method.to_string(),
+            reject_code: code as u32,
+            message,
+            // Only transient system rejections (e.g. a full queue) may succeed on retry.
+            retryable: code == RejectionCode::SysTransient,
+        }
+    }
+}
+
+impl ErrorClass for CallFailure {
+    fn code(&self) -> &'static str {
+        match self.reject_code {
+            1 => "sys_fatal",
+            2 => "sys_transient",
+            3 => "destination_invalid",
+            4 => "canister_reject",
+            5 => "canister_error",
+            _ => "unknown",
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+}
+
+impl fmt::Display for CallFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on {} rejected ({}): {}",
+            self.method,
+            self.canister,
+            self.code(),
+            self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_transient_rejections_are_retryable() {
+        let canister = Principal::anonymous();
+        let transient = CallFailure::new(canister, "import_items", (RejectionCode::SysTransient, "full".into()));
+        assert!(transient.is_retryable());
+        assert_eq!(transient.code(), "sys_transient");
+        assert_eq!(transient.reject_code, 2);
+
+        let trapped = CallFailure::new(canister, "import_items", (RejectionCode::CanisterError, "trapped".into()));
+        assert!(!trapped.is_retryable());
+        assert_eq!(trapped.code(), "canister_error");
+        let expected = format!("import_items on {} rejected (canister_error): trapped", canister);
+        assert_eq!(trapped.to_string(), expected);
+    }
+}
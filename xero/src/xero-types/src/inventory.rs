@@ -0,0 +1,164 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+use crate::{ErrorClass, Money, Quantity, UnitOfMeasure};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemStatus {
+    Active,
+    ExpiringSoon,
+    Expired,
+    LowStock,
+    OutOfStock,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AuditLog {
+    pub timestamp: u64,
+    pub action: String,
+    pub details: String,
+    pub actor: String,
+}
+
+/// A purchasable pack, e.g. a case holding 12 each.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PackSize {
+    pub pack_id: String,
+    pub contains: Quantity,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct InventoryItem {
+    pub item_id: String,
+    pub barcode: String,
+    pub name: String,
+    pub category: Option<String>, // category_id in the category registry
+    pub quantity: Quantity, // fixed-point; weighted items may hold fractions
+    pub expiration_date: u64,
+    pub price: Money, // per one unit of quantity.unit, e.g. per kg
+    pub last_updated: u64,
+    pub status: ItemStatus,
+    pub audit_trail: Vec<AuditLog>,
+    pub pack_sizes: Vec<PackSize>,
+}
+
+/// The fields a caller supplies when adding or updating an item; inventory derives the rest.
+/// Imports carry the source's category label in `category` until it is resolved to a category_id.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct InventoryItemInput {
+    pub item_id: String,
+    pub barcode: String,
+    pub name: String,
+    pub category: Option<String>,
+    pub quantity: Quantity,
+    pub expiration_date: u64, // 0 falls back to the category's default shelf life
+    pub price: Money, // per one unit of quantity.unit
+}
+
+/// The fields other canisters need about an item, returned by inventory's `get_item_details`.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ItemDetails {
+    pub item_id: String,
+    pub category: Option<String>,
+    pub quantity: Quantity,
+    pub expiration_date: u64,
+    pub price: Money, // per one `unit`
+    pub unit: UnitOfMeasure,
+    pub status: ItemStatus,
+}
+
+impl From<&InventoryItem> for ItemDetails {
+    fn from(item: &InventoryItem) -> Self {
+        ItemDetails {
+            item_id: item.item_id.clone(),
+            category: item.category.clone(),
+            quantity: item.quantity,
+            expiration_date: item.expiration_date,
+            price: item.price.clone(),
+            unit: item.quantity.unit,
+            status: item.status,
+        }
+    }
+}
+
+/// A change to an item's stock reported after the fact, e.g. by a POS system, rather than a new level.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum StockMovement {
+    /// `quantity` sold at `unit_price` per one unit of `quantity.unit`; stock goes down.
+    Sale { item_id: String, quantity: Quantity, unit_price: Money },
+    /// Stock corrected up or down by `quantity`, e.g. a delivery or breakage found at the till.
+    Adjustment { item_id: String, quantity: Quantity, increase: bool, reason: String },
+    /// Stock thrown away, e.g. expired or damaged goods; stock goes down and the ledger records waste.
+    Disposal { item_id: String, quantity: Quantity, reason: String },
+}
+
+impl StockMovement {
+    pub fn item_id(&self) -> &str {
+        match self {
+            StockMovement::Sale { item_id, .. }
+            | StockMovement::Adjustment { item_id, .. }
+            | StockMovement::Disposal { item_id, .. } => item_id,
+        }
+    }
+}
+
+/// An item's stock before and after a movement was applied, in the item's own unit.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct StockLevelChange {
+    pub item_id: String,
+    pub old_quantity: Quantity,
+    pub new_quantity: Quantity,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum StorageTemperature {
+    Ambient,
+    Chilled,
+    Frozen,
+}
+
+/// A node in the category tree, e.g. Cheese with parent Dairy.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ItemCategory {
+    pub category_id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub default_shelf_life_days: Option<u32>,
+    pub storage_temperature: Option<StorageTemperature>,
+}
+
+/// Errors returned by the inventory canister API.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum InventoryError {
+    ItemNotFound { item_id: String },
+    BarcodeNotFound { barcode: String },
+    CategoryNotFound { category_id: String },
+    InvalidInput { field: String, reason: String },
+    Unauthorized { caller: Principal },
+}
+
+impl ErrorClass for InventoryError {
+    fn code(&self) -> &'static str {
+        match self {
+            InventoryError::ItemNotFound { .. } => "item_not_found",
+            InventoryError::BarcodeNotFound { .. } => "barcode_not_found",
+            InventoryError::CategoryNotFound { .. } => "category_not_found",
+            InventoryError::InvalidInput { .. } => "invalid_input",
+            InventoryError::Unauthorized { .. } => "unauthorized",
+        }
+    }
+
+    // Every inventory error is decided by the request and the current state; resending it unchanged fails again
+    fn is_retryable(&self) -> bool {
+        false
+    }
+}
+
+impl InventoryError {
+    pub fn invalid(field: &str, reason: &str) -> Self {
+        InventoryError::InvalidInput {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
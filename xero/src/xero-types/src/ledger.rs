@@ -0,0 +1,166 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::{Money, Quantity};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InventoryOperation {
+    Added,
+    Updated,
+    Removed,
+}
+
+/// A block in the ledger, held by the ledger canister or one of its archives.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Transaction {
+    pub transaction_id: u64, // Assigned by the ledger, increasing by one per transaction
+    pub timestamp: u64, // Unix timestamp
+    pub action_type: String, // TransactionKind variant name, or the caller's label for legacy entries
+    pub details: String, // Human-readable summary of `kind`
+    pub actor_id: String, // ID of the actor who initiated the transaction
+    pub item_ids: Vec<String>, // Inventory items the transaction refers to
+    pub kind: TransactionKind,
+    pub idempotency_key: Option<String>, // Caller-supplied key used to dedupe retries
+}
+
+/// A pricing rule as it stood before or after an update.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PriceRuleState {
+    pub description: String,
+    pub active: bool,
+}
+
+/// Typed payload of a ledger transaction.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum TransactionKind {
+    PriceChange {
+        item_id: String,
+        old_value: Money,
+        new_value: Money,
+        reason: String,
+    },
+    BulkPriceChange {
+        item_ids: Vec<String>,
+        reason: String,
+    },
+    PriceRuleUpdate {
+        rule_name: String,
+        old_rule: Option<PriceRuleState>,
+        new_rule: PriceRuleState,
+    },
+    InventoryUpdate {
+        item_id: String,
+        operation: InventoryOperation,
+    },
+    Sale {
+        item_id: String,
+        quantity: Quantity,
+        unit_price: Money,
+    },
+    StockAdjustment {
+        item_id: String,
+        old_quantity: Quantity,
+        new_quantity: Quantity,
+        reason: String,
+    },
+    /// Stock written off and thrown away, e.g. because it expired or was damaged.
+    WasteDisposal {
+        item_id: String,
+        quantity: Quantity,
+        reason: String,
+    },
+    ImportBatch {
+        batch_id: String,
+        records_count: u32,
+        success_count: u32,
+        error_count: u32,
+    },
+    /// A manager's decision on stock counts entered by hand; `actor_id` is the reviewer.
+    ManualEntryReview {
+        batch_id: String,
+        submitted_by: String,
+        approved: bool,
+        item_ids: Vec<String>,
+        comment: String,
+    },
+    /// Free-form entry written through the original string-based API.
+    Legacy { details: String },
+}
+
+impl TransactionKind {
+    /// Name of the variant, used as the transaction's `action_type`.
+    pub fn action_type(&self) -> &'static str {
+        match self {
+            TransactionKind::PriceChange { .. } => "PriceChange",
+            TransactionKind::BulkPriceChange { .. } => "BulkPriceChange",
+            TransactionKind::PriceRuleUpdate { .. } => "PriceRuleUpdate",
+            TransactionKind::InventoryUpdate { .. } => "InventoryUpdate",
+            TransactionKind::Sale { .. } => "Sale",
+            TransactionKind::StockAdjustment { .. } => "StockAdjustment",
+            TransactionKind::WasteDisposal { .. } => "WasteDisposal",
+            TransactionKind::ImportBatch { .. } => "ImportBatch",
+            TransactionKind::ManualEntryReview { .. } => "ManualEntryReview",
+            TransactionKind::Legacy { .. } => "Legacy",
+        }
+    }
+
+    /// Inventory items the payload refers to.
+    pub fn item_ids(&self) -> Vec<String> {
+        match self {
+            TransactionKind::PriceChange { item_id, .. }
+            | TransactionKind::InventoryUpdate { item_id, .. }
+            | TransactionKind::Sale { item_id, .. }
+            | TransactionKind::StockAdjustment { item_id, .. }
+            | TransactionKind::WasteDisposal { item_id, .. } => vec![item_id.clone()],
+            TransactionKind::BulkPriceChange { item_ids, .. }
+            | TransactionKind::ManualEntryReview { item_ids, .. } => item_ids.clone(),
+            TransactionKind::PriceRuleUpdate { .. }
+            | TransactionKind::ImportBatch { .. }
+            | TransactionKind::Legacy { .. } => Vec::new(),
+        }
+    }
+
+    /// One-line human-readable description.
+    pub fn summary(&self) -> String {
+        match self {
+            TransactionKind::PriceChange { item_id, old_value, new_value, reason } => {
+                format!("Price of '{}' changed from {} to {} ({})", item_id, old_value, new_value, reason)
+            }
+            TransactionKind::BulkPriceChange { item_ids, reason } => {
+                format!("Prices of {} items changed ({})", item_ids.len(), reason)
+            }
+            TransactionKind::PriceRuleUpdate { rule_name, new_rule, .. } => format!(
+                "Pricing rule '{}' {}: {}",
+                rule_name,
+                if new_rule.active { "active" } else { "inactive" },
+                new_rule.description
+            ),
+            TransactionKind::InventoryUpdate { item_id, operation } => {
+                format!("Item '{}' {:?}", item_id, operation)
+            }
+            TransactionKind::Sale { item_id, quantity, unit_price } => {
+                format!("Sold {} of '{}' at {} per {}", quantity, item_id, unit_price, quantity.unit.symbol())
+            }
+            TransactionKind::StockAdjustment { item_id, old_quantity, new_quantity, reason } => format!(
+                "Stock of '{}' adjusted from {} to {} ({})",
+                item_id, old_quantity, new_quantity, reason
+            ),
+            TransactionKind::WasteDisposal { item_id, quantity, reason } => {
+                format!("Disposed of {} of '{}' ({})", quantity, item_id, reason)
+            }
+            TransactionKind::ImportBatch { batch_id, records_count, success_count, error_count } => format!(
+                "Batch '{}' imported {} of {} records ({} errors)",
+                batch_id, success_count, records_count, error_count
+            ),
+            TransactionKind::ManualEntryReview { batch_id, submitted_by, approved, item_ids, comment } => format!(
+                "Manual entry batch '{}' of {} items from {} {} ({})",
+                batch_id,
+                item_ids.len(),
+                submitted_by,
+                if *approved { "approved" } else { "rejected" },
+                comment
+            ),
+            TransactionKind::Legacy { details } => details.clone(),
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Candid types shared between the Xero canisters.
+
+pub mod config;
+pub mod error;
+pub mod inventory;
+pub mod ledger;
+pub mod money;
+pub mod units;
+
+pub use config::Dependencies;
+pub use error::{CallFailure, ErrorClass};
+pub use inventory::{InventoryItem, InventoryItemInput, ItemCategory, ItemStatus};
+pub use ledger::{Transaction, TransactionKind};
+pub use money::{Money, RoundingMode};
+pub use units::{Quantity, UnitOfMeasure};
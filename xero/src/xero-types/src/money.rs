@@ -0,0 +1,196 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::fmt;
+
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// How a fractional minor-unit amount is turned into a whole one.
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    HalfUp,
+    HalfEven,
+    Down,
+    Up,
+    /// Nearest price ending in these minor units, e.g. `PriceEnding(99)` gives x.99 prices.
+    PriceEnding(u64),
+}
+
+/// An amount in integer minor units (cents for USD) of an ISO 4217 currency.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Money {
+    pub minor_units: u64,
+    pub currency: String,
+}
+
+/// Number of minor-unit digits for an ISO 4217 code.
+pub fn currency_exponent(currency: &str) -> u32 {
+    match currency {
+        "JPY" | "KRW" | "VND" | "CLP" | "ISK" => 0,
+        "BHD" | "KWD" | "OMR" | "JOD" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+pub fn is_valid_currency(currency: &str) -> bool {
+    currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase())
+}
+
+/// Parses a plain decimal such as "12.345" into units of 10^-`exponent`, rounding extra digits half up.
+/// Only ASCII digits and at most one '.' are accepted; `None` for anything else or on overflow.
+pub fn parse_decimal(value: &str, exponent: u32) -> Option<u64> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let digits_only = whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit());
+    if !digits_only || (whole.is_empty() && fraction.is_empty()) {
+        return None;
+    }
+    let padded = fraction.bytes().chain(std::iter::repeat(b'0')).take(exponent as usize);
+    let mut scaled: u64 = 0;
+    for digit in whole.bytes().chain(padded) {
+        scaled = scaled.checked_mul(10)?.checked_add(u64::from(digit - b'0'))?;
+    }
+    let round_up = fraction.as_bytes().get(exponent as usize).is_some_and(|&digit| digit >= b'5');
+    scaled.checked_add(u64::from(round_up))
+}
+
+fn round_ratio(numerator: u128, denominator: u128, mode: RoundingMode, minor_per_major: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    let rounded = match mode {
+        RoundingMode::Down => quotient,
+        RoundingMode::Up => quotient + u128::from(remainder > 0),
+        RoundingMode::HalfUp => quotient + u128::from(2 * remainder >= denominator),
+        RoundingMode::HalfEven => {
+            if 2 * remainder > denominator || (2 * remainder == denominator && quotient % 2 == 1) {
+                quotient + 1
+            } else {
+                quotient
+            }
+        }
+        RoundingMode::PriceEnding(ending) => {
+            let ending = u128::from(ending);
+            if ending >= minor_per_major {
+                return None;
+            }
+            if numerator <= ending * denominator {
+                return Some(ending);
+            }
+            // Candidates are k * minor_per_major + ending; pick the nearer one, ties go down
+            let lower = (numerator - ending * denominator) / (denominator * minor_per_major) * minor_per_major + ending;
+            let upper = lower + minor_per_major;
+            if 2 * numerator <= denominator * (lower + upper) {
+                lower
+            } else {
+                upper
+            }
+        }
+    };
+    Some(rounded)
+}
+
+impl Money {
+    pub fn new(minor_units: u64, currency: &str) -> Self {
+        Money {
+            minor_units,
+            currency: currency.to_string(),
+        }
+    }
+
+    pub fn minor_per_major(&self) -> u64 {
+        10u64.pow(currency_exponent(&self.currency))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor_units == 0
+    }
+
+    pub fn is_valid(&self) -> bool {
+        is_valid_currency(&self.currency)
+    }
+
+    /// Parses a decimal amount in major units, e.g. "6.993", rounding half up to the nearest minor unit.
+    pub fn from_major_str(amount: &str, currency: &str) -> Option<Money> {
+        parse_decimal(amount, currency_exponent(currency)).map(|minor| Money::new(minor, currency))
+    }
+
+    /// Multiplies by `numerator / denominator` with a single rounding step at the end.
+    pub fn scale(&self, numerator: u64, denominator: u64, mode: RoundingMode) -> Option<Money> {
+        let exact = u128::from(self.minor_units) * u128::from(numerator);
+        let rounded = round_ratio(exact, u128::from(denominator), mode, u128::from(self.minor_per_major()))?;
+        Some(Money {
+            minor_units: u64::try_from(rounded).ok()?,
+            currency: self.currency.clone(),
+        })
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exponent = currency_exponent(&self.currency) as usize;
+        if exponent == 0 {
+            return write!(f, "{} {}", self.minor_units, self.currency);
+        }
+        let minor_per_major = self.minor_per_major();
+        write!(
+            f,
+            "{}.{:0width$} {}",
+            self.minor_units / minor_per_major,
+            self.minor_units % minor_per_major,
+            self.currency,
+            width = exponent
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(minor_units: u64) -> Money {
+        Money::new(minor_units, "USD")
+    }
+
+    #[test]
+    fn half_even_rounds_ties_to_the_even_neighbour() {
+        assert_eq!(round_ratio(25, 10, RoundingMode::HalfEven, 100), Some(2));
+        assert_eq!(round_ratio(35, 10, RoundingMode::HalfEven, 100), Some(4));
+        assert_eq!(round_ratio(26, 10, RoundingMode::HalfEven, 100), Some(3));
+        assert_eq!(round_ratio(25, 10, RoundingMode::HalfUp, 100), Some(3));
+        assert_eq!(round_ratio(29, 10, RoundingMode::Down, 100), Some(2));
+        assert_eq!(round_ratio(21, 10, RoundingMode::Up, 100), Some(3));
+        assert_eq!(round_ratio(1, 0, RoundingMode::HalfUp, 100), None);
+    }
+
+    #[test]
+    fn price_ending_picks_the_nearer_candidate_and_ties_go_down() {
+        let x99 = RoundingMode::PriceEnding(99);
+        // 699.3 cents sits between 6.99 and 7.99
+        assert_eq!(round_ratio(6993, 10, x99, 100), Some(699));
+        // 7.50 is nearer 7.99; 7.49 is exactly halfway and goes down
+        assert_eq!(round_ratio(7500, 10, x99, 100), Some(799));
+        assert_eq!(round_ratio(7490, 10, x99, 100), Some(699));
+        // Anything up to the ending itself becomes the lowest price with that ending
+        assert_eq!(round_ratio(500, 10, x99, 100), Some(99));
+        assert_eq!(round_ratio(1, 1, RoundingMode::PriceEnding(100), 100), None);
+    }
+
+    #[test]
+    fn scale_reports_overflow_instead_of_wrapping() {
+        assert_eq!(usd(u64::MAX).scale(2, 1, RoundingMode::HalfUp), None);
+        assert_eq!(usd(u64::MAX).scale(1, 2, RoundingMode::Down), Some(usd(u64::MAX / 2)));
+        assert_eq!(usd(999).scale(7, 10, RoundingMode::HalfUp), Some(usd(699)));
+        assert_eq!(usd(999).scale(1, 0, RoundingMode::HalfUp), None);
+    }
+
+    #[test]
+    fn major_amounts_round_half_up_to_minor_units() {
+        assert_eq!(Money::from_major_str("6.993", "USD"), Some(usd(699)));
+        assert_eq!(Money::from_major_str("6.995", "USD"), Some(usd(700)));
+        assert_eq!(Money::from_major_str("1234.5", "JPY"), Some(Money::new(1235, "JPY")));
+        assert_eq!(Money::from_major_str("-1.0", "USD"), None);
+        assert_eq!(Money::from_major_str("NaN", "USD"), None);
+        assert_eq!(Money::from_major_str("100000000000000000000000000000", "USD"), None);
+    }
+}
@@ -0,0 +1,155 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::fmt;
+
+/// Number of fixed-point steps per whole unit (three decimal places).
+pub const MILLI_PER_UNIT: u64 = 1_000;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnitOfMeasure {
+    Each,
+    Kilogram,
+    Gram,
+    Pound,
+    Liter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dimension {
+    Count,
+    Mass,
+    Volume,
+}
+
+impl UnitOfMeasure {
+    fn dimension(self) -> Dimension {
+        match self {
+            UnitOfMeasure::Each => Dimension::Count,
+            UnitOfMeasure::Kilogram | UnitOfMeasure::Gram | UnitOfMeasure::Pound => Dimension::Mass,
+            UnitOfMeasure::Liter => Dimension::Volume,
+        }
+    }
+
+    // Size of one milli-unit in the dimension's base unit (nanograms for mass)
+    fn base_factor(self) -> u128 {
+        match self {
+            UnitOfMeasure::Each | UnitOfMeasure::Liter => 1,
+            UnitOfMeasure::Kilogram => 1_000_000_000,
+            UnitOfMeasure::Gram => 1_000_000,
+            UnitOfMeasure::Pound => 453_592_370,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnitOfMeasure::Each => "each",
+            UnitOfMeasure::Kilogram => "kg",
+            UnitOfMeasure::Gram => "g",
+            UnitOfMeasure::Pound => "lb",
+            UnitOfMeasure::Liter => "L",
+        }
+    }
+
+    /// Weighted units may hold fractional quantities; `Each` may not.
+    pub fn is_weighted(self) -> bool {
+        self.dimension() != Dimension::Count
+    }
+}
+
+/// Fixed-point quantity with three decimals: 1.25 kg is `{ milli_units: 1250, unit: Kilogram }`.
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quantity {
+    pub milli_units: u64,
+    pub unit: UnitOfMeasure,
+}
+
+impl Quantity {
+    pub fn whole(units: u64, unit: UnitOfMeasure) -> Self {
+        Quantity {
+            milli_units: units.saturating_mul(MILLI_PER_UNIT),
+            unit,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.milli_units == 0
+    }
+
+    pub fn is_whole(&self) -> bool {
+        self.milli_units.is_multiple_of(MILLI_PER_UNIT)
+    }
+
+    pub fn whole_units(&self) -> u64 {
+        self.milli_units / MILLI_PER_UNIT
+    }
+
+    /// `Each` quantities must be whole numbers.
+    pub fn is_valid(&self) -> bool {
+        self.unit.is_weighted() || self.is_whole()
+    }
+
+    /// Converts between units of the same dimension, rounding half up to the nearest milli-unit.
+    pub fn convert_to(&self, unit: UnitOfMeasure) -> Option<Quantity> {
+        if self.unit.dimension() != unit.dimension() {
+            return None;
+        }
+        let base = u128::from(self.milli_units) * self.unit.base_factor();
+        let factor = unit.base_factor();
+        let milli_units = u64::try_from((base + factor / 2) / factor).ok()?;
+        Some(Quantity { milli_units, unit })
+    }
+
+    pub fn checked_mul(&self, count: u64) -> Option<Quantity> {
+        Some(Quantity {
+            milli_units: self.milli_units.checked_mul(count)?,
+            unit: self.unit,
+        })
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_whole() {
+            write!(f, "{} {}", self.whole_units(), self.unit.symbol())
+        } else {
+            write!(
+                f,
+                "{}.{:03} {}",
+                self.whole_units(),
+                self.milli_units % MILLI_PER_UNIT,
+                self.unit.symbol()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn milli(milli_units: u64, unit: UnitOfMeasure) -> Quantity {
+        Quantity { milli_units, unit }
+    }
+
+    #[test]
+    fn convert_to_rounds_half_up_to_the_nearest_milli_unit() {
+        let pound = Quantity::whole(1, UnitOfMeasure::Pound);
+        assert_eq!(pound.convert_to(UnitOfMeasure::Kilogram), Some(milli(454, UnitOfMeasure::Kilogram)));
+        assert_eq!(
+            Quantity::whole(1, UnitOfMeasure::Kilogram).convert_to(UnitOfMeasure::Gram),
+            Some(Quantity::whole(1_000, UnitOfMeasure::Gram))
+        );
+        // 0.5 g is half a milli-kilogram
+        let half_gram = milli(500, UnitOfMeasure::Gram);
+        assert_eq!(half_gram.convert_to(UnitOfMeasure::Kilogram), Some(milli(1, UnitOfMeasure::Kilogram)));
+        let under_half = milli(499, UnitOfMeasure::Gram);
+        assert_eq!(under_half.convert_to(UnitOfMeasure::Kilogram), Some(milli(0, UnitOfMeasure::Kilogram)));
+    }
+
+    #[test]
+    fn convert_to_rejects_other_dimensions_and_overflow() {
+        assert_eq!(Quantity::whole(3, UnitOfMeasure::Each).convert_to(UnitOfMeasure::Kilogram), None);
+        assert_eq!(Quantity::whole(1, UnitOfMeasure::Liter).convert_to(UnitOfMeasure::Gram), None);
+        assert_eq!(milli(u64::MAX, UnitOfMeasure::Kilogram).convert_to(UnitOfMeasure::Gram), None);
+    }
+}